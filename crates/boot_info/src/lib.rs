@@ -0,0 +1,315 @@
+#![no_std]
+
+//shared between the bootloader and the kernel, everything in here has to stay #[repr(C)]
+//and any change to the layout has to bump BOOT_INFO_VERSION
+
+pub const BOOT_INFO_MAGIC: u64 = 0x4f464e49_544f4f42; //"BOOTINFO"
+pub const BOOT_INFO_VERSION: u32 = 13;
+
+#[repr(C)]
+pub struct BootInfo {
+    pub magic: u64,
+    pub version: u32,
+    pub size: u32,
+    //all of physical memory is mapped starting here
+    pub physical_memory_offset: u64,
+    //added to every address the kernel was linked at, 0 unless it's position independent
+    pub kernel_slide: u64,
+    pub memory_regions: Slice<MemoryRegion>,
+    pub cmdline: Slice<u8>,
+    pub framebuffer: Optional<FrameBufferInfo>,
+    pub initrd: Optional<Module>,
+    //files from efi\kernel\modules, sorted by name
+    pub modules: Slice<Module>,
+    pub kernel_measurement: Measurement,
+    pub initrd_measurement: Optional<Measurement>,
+    //the boot cpu's thread pointer (fs base, tp) already points at a block made from this
+    pub tls_template: Optional<TlsTemplate>,
+    //physical addresses from the uefi configuration table. the rsdp is 2.0 if the firmware has it,
+    //and the smbios entry point is 3 if it has that, check the anchor for "_SM3_" vs "_SM_"
+    pub rsdp_addr: Optional<u64>,
+    pub smbios_addr: Optional<u64>,
+    //virtual address of the uefi runtime services table, already switched to the kernel's page table
+    pub runtime_services_addr: Optional<u64>,
+    //physical address of the flattened device tree, where the firmware has one
+    pub device_tree_addr: Optional<u64>,
+    //the kernel's .symtab and the .strtab its names point into, mapped read only. empty if the kernel
+    //was stripped
+    pub symbols: Slice<Symbol>,
+    pub symbol_names: Slice<u8>,
+}
+
+#[derive(Debug)]
+pub enum BootInfoError {
+    BadMagic(u64),
+    VersionMismatch { expected: u32, found: u32 },
+    SizeMismatch { expected: u32, found: u32 },
+}
+
+impl BootInfo {
+    pub fn check(&self) -> Result<(), BootInfoError> {
+        if self.magic != BOOT_INFO_MAGIC {
+            return Err(BootInfoError::BadMagic(self.magic));
+        }
+        if self.version != BOOT_INFO_VERSION {
+            return Err(BootInfoError::VersionMismatch {
+                expected: BOOT_INFO_VERSION,
+                found: self.version,
+            });
+        }
+        let size = core::mem::size_of::<BootInfo>() as u32;
+        if self.size != size {
+            return Err(BootInfoError::SizeMismatch {
+                expected: size,
+                found: self.size,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn memory_regions(&self) -> &[MemoryRegion] {
+        unsafe { self.memory_regions.as_slice() }
+    }
+
+    pub fn cmdline(&self) -> Option<&str> {
+        core::str::from_utf8(unsafe { self.cmdline.as_slice() }).ok()
+    }
+
+    pub fn framebuffer(&self) -> Option<&FrameBufferInfo> {
+        self.framebuffer.as_option()
+    }
+
+    pub fn initrd(&self) -> Option<&Module> {
+        self.initrd.as_option()
+    }
+
+    pub fn modules(&self) -> &[Module] {
+        unsafe { self.modules.as_slice() }
+    }
+
+    pub fn tls_template(&self) -> Option<&TlsTemplate> {
+        self.tls_template.as_option()
+    }
+
+    pub fn rsdp_addr(&self) -> Option<u64> {
+        self.rsdp_addr.as_option().copied()
+    }
+
+    pub fn smbios_addr(&self) -> Option<u64> {
+        self.smbios_addr.as_option().copied()
+    }
+
+    pub fn runtime_services_addr(&self) -> Option<u64> {
+        self.runtime_services_addr.as_option().copied()
+    }
+
+    pub fn device_tree_addr(&self) -> Option<u64> {
+        self.device_tree_addr.as_option().copied()
+    }
+
+    pub fn symbols(&self) -> &[Symbol] {
+        unsafe { self.symbols.as_slice() }
+    }
+
+    //names are nul terminated, and not always utf-8 if the kernel links in something odd
+    pub fn symbol_name(&self, symbol: &Symbol) -> Option<&str> {
+        let names = unsafe { self.symbol_names.as_slice() };
+        let name = names.get(symbol.name as usize..)?;
+        let len = name.iter().position(|&b| b == 0)?;
+        core::str::from_utf8(&name[..len]).ok()
+    }
+}
+
+//core::option::Option has no stable layout, so this stands in for it across the handoff
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Optional<T> {
+    Some(T),
+    None,
+}
+
+impl<T> Optional<T> {
+    pub fn as_option(&self) -> Option<&T> {
+        match self {
+            Optional::Some(val) => Some(val),
+            Optional::None => None,
+        }
+    }
+}
+
+impl<T> From<Option<T>> for Optional<T> {
+    fn from(val: Option<T>) -> Self {
+        match val {
+            Some(val) => Optional::Some(val),
+            None => Optional::None,
+        }
+    }
+}
+
+//pointers are virtual addresses in the kernel's address space, not the bootloader's
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Slice<T> {
+    ptr: *const T,
+    len: u64,
+}
+
+impl<T> Slice<T> {
+    pub const fn new(ptr: *const T, len: u64) -> Self {
+        Slice { ptr, len }
+    }
+
+    pub const fn empty() -> Self {
+        Slice {
+            ptr: core::ptr::null(),
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// # Safety
+    /// must be called from the kernel's address space, after the bootloader has mapped it
+    pub unsafe fn as_slice(&self) -> &[T] {
+        if self.len == 0 {
+            return &[];
+        }
+        unsafe { core::slice::from_raw_parts(self.ptr, self.len as usize) }
+    }
+}
+
+//only ever points at memory the bootloader handed over and never touches again
+unsafe impl<T: Sync> Sync for Slice<T> {}
+unsafe impl<T: Send> Send for Slice<T> {}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub start: u64,
+    pub end: u64,
+    pub kind: MemoryRegionKind,
+}
+
+//the kernel's PT_TLS segment. a block is file_size bytes copied from start_addr, then zeroes up to
+//mem_size. the thread pointer goes right after it at an align boundary on x86_64 (variant II), and
+//right at its start on riscv64 (variant I)
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TlsTemplate {
+    pub start_addr: u64,
+    pub file_size: u64,
+    pub mem_size: u64,
+    pub align: u64,
+}
+
+//an Elf64_Sym as it is in the kernel file. value is the address the kernel was linked at, add
+//kernel_slide to get where it is now
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Symbol {
+    //offset into symbol_names
+    pub name: u32,
+    pub info: u8,
+    pub other: u8,
+    pub shndx: u16,
+    pub value: u64,
+    pub size: u64,
+}
+
+impl Symbol {
+    pub fn is_function(&self) -> bool {
+        self.info & 0xf == 2 //STT_FUNC
+    }
+}
+
+//what the bootloader checked before booting, a failed check never gets this far
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Measurement {
+    pub status: VerifyStatus,
+    pub sha512: [u8; 64],
+}
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VerifyStatus {
+    //the ed25519 signature matched the key compiled into the bootloader
+    Verified,
+    //the bootloader was built without a key, only the hash is known
+    Unchecked,
+}
+
+//a file the bootloader loaded next to the kernel, mapped read only
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Module {
+    pub name: Slice<u8>,
+    pub start: u64,
+    pub phys_start: u64,
+    pub len: u64,
+}
+
+impl Module {
+    pub fn name(&self) -> Option<&str> {
+        core::str::from_utf8(unsafe { self.name.as_slice() }).ok()
+    }
+
+    pub fn data(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        unsafe { core::slice::from_raw_parts(self.start as *const u8, self.len as usize) }
+    }
+}
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryRegionKind {
+    Usable,
+    //frames the bootloader allocated for the kernel: page tables, stack, boot info
+    Bootloader,
+    //the kernel's load segments
+    Kernel,
+    //the initrd and other modules, free once the kernel is done with their contents
+    Module,
+    //bootloader and firmware boot services memory, free once the kernel stops using the firmware's gdt/idt
+    Reclaimable,
+    RuntimeServicesCode,
+    RuntimeServicesData,
+    AcpiReclaimable,
+    AcpiNvs,
+    Mmio,
+    Persistent,
+    Unusable,
+    Reserved,
+}
+
+//linear framebuffer from the firmware's graphics output protocol, always 32 bits per pixel
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameBufferInfo {
+    //physical address, the kernel reaches it through the physical memory offset
+    pub addr: u64,
+    pub size: u64,
+    pub width: u32,
+    pub height: u32,
+    //in pixels, can be bigger than the width
+    pub stride: u32,
+    pub pixel_format: PixelFormat,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelFormat {
+    //red in the lowest byte
+    Rgb,
+    //blue in the lowest byte
+    Bgr,
+    Bitmask { red: u32, green: u32, blue: u32 },
+}
@@ -0,0 +1,10 @@
+fn main() {
+    match std::env::var("TARGET").unwrap().as_str() {
+        "x86_64-unknown-uefi" => {}
+        //scripts/riscv64/build.sh also makes it position independent and turns it into a .efi
+        "riscv64gc-unknown-none-elf" => {
+            println!("cargo:rustc-link-arg=-T./crates/bootloader/src/arch/riscv64/linker.ld")
+        }
+        arch => panic!("unsupported arch: {}", arch),
+    }
+}
@@ -0,0 +1,200 @@
+//addresses, pages and frames are just numbers on every arch, so the generic code gets its own
+//instead of borrowing the x86_64 crate's. only 4KiB pages and frames, bigger mappings go through
+//MapSize
+use core::fmt;
+use core::ops::{Add, AddAssign, Sub};
+
+pub const PAGE_SIZE: u64 = 0x1000;
+
+pub const fn align_down(addr: u64, align: u64) -> u64 {
+    assert!(align.is_power_of_two());
+    addr & !(align - 1)
+}
+
+pub const fn align_up(addr: u64, align: u64) -> u64 {
+    assert!(align.is_power_of_two());
+    match addr.checked_add(align - 1) {
+        Some(addr) => addr & !(align - 1),
+        None => panic!("aligning up overflowed"),
+    }
+}
+
+macro_rules! address {
+    ($name:ident) => {
+        #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+        #[repr(transparent)]
+        pub struct $name(u64);
+
+        impl $name {
+            pub const fn new(addr: u64) -> Self {
+                $name(addr)
+            }
+
+            pub const fn as_u64(self) -> u64 {
+                self.0
+            }
+
+            pub const fn align_down(self, align: u64) -> Self {
+                $name(align_down(self.0, align))
+            }
+
+            pub const fn align_up(self, align: u64) -> Self {
+                $name(align_up(self.0, align))
+            }
+
+            pub const fn is_aligned(self, align: u64) -> bool {
+                align_down(self.0, align) == self.0
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, concat!(stringify!($name), "({:#x})"), self.0)
+            }
+        }
+
+        impl Add<u64> for $name {
+            type Output = Self;
+            fn add(self, rhs: u64) -> Self {
+                $name(self.0.checked_add(rhs).expect("address overflowed"))
+            }
+        }
+
+        impl AddAssign<u64> for $name {
+            fn add_assign(&mut self, rhs: u64) {
+                *self = *self + rhs;
+            }
+        }
+
+        impl Sub<u64> for $name {
+            type Output = Self;
+            fn sub(self, rhs: u64) -> Self {
+                $name(self.0.checked_sub(rhs).expect("address underflowed"))
+            }
+        }
+
+        impl Sub<$name> for $name {
+            type Output = u64;
+            fn sub(self, rhs: $name) -> u64 {
+                self.0.checked_sub(rhs.0).expect("address underflowed")
+            }
+        }
+    };
+}
+
+address!(PhysAddr);
+address!(VirtAddr);
+
+impl VirtAddr {
+    pub fn as_ptr<T>(self) -> *const T {
+        self.0 as *const T
+    }
+
+    pub fn as_mut_ptr<T>(self) -> *mut T {
+        self.0 as *mut T
+    }
+}
+
+//a 4KiB physical frame
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PhysFrame(PhysAddr);
+
+impl PhysFrame {
+    pub fn from_start_address(addr: PhysAddr) -> Result<Self, ()> {
+        if addr.is_aligned(PAGE_SIZE) {
+            Ok(PhysFrame(addr))
+        } else {
+            Err(())
+        }
+    }
+
+    pub fn containing_address(addr: PhysAddr) -> Self {
+        PhysFrame(addr.align_down(PAGE_SIZE))
+    }
+
+    pub fn start_address(self) -> PhysAddr {
+        self.0
+    }
+
+    pub fn size(self) -> u64 {
+        PAGE_SIZE
+    }
+}
+
+impl fmt::Debug for PhysFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PhysFrame({:#x})", self.0.as_u64())
+    }
+}
+
+impl Add<u64> for PhysFrame {
+    type Output = Self;
+    fn add(self, rhs: u64) -> Self {
+        PhysFrame(self.0 + rhs * PAGE_SIZE)
+    }
+}
+
+impl AddAssign<u64> for PhysFrame {
+    fn add_assign(&mut self, rhs: u64) {
+        *self = *self + rhs;
+    }
+}
+
+//a 4KiB virtual page
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Page(VirtAddr);
+
+impl Page {
+    pub fn from_start_address(addr: VirtAddr) -> Result<Self, ()> {
+        if addr.is_aligned(PAGE_SIZE) {
+            Ok(Page(addr))
+        } else {
+            Err(())
+        }
+    }
+
+    pub fn containing_address(addr: VirtAddr) -> Self {
+        Page(addr.align_down(PAGE_SIZE))
+    }
+
+    pub fn start_address(self) -> VirtAddr {
+        self.0
+    }
+
+    //end isn't included
+    pub fn range(start: Page, end: Page) -> impl Iterator<Item = Page> {
+        (start.0.as_u64()..end.0.as_u64())
+            .step_by(PAGE_SIZE as usize)
+            .map(|addr| Page(VirtAddr::new(addr)))
+    }
+
+    pub fn range_inclusive(start: Page, end: Page) -> impl Iterator<Item = Page> {
+        Page::range(start, end + 1)
+    }
+}
+
+impl fmt::Debug for Page {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Page({:#x})", self.0.as_u64())
+    }
+}
+
+impl Add<u64> for Page {
+    type Output = Self;
+    fn add(self, rhs: u64) -> Self {
+        Page(self.0 + rhs * PAGE_SIZE)
+    }
+}
+
+//how many pages apart
+impl Sub<Page> for Page {
+    type Output = u64;
+    fn sub(self, rhs: Page) -> u64 {
+        (self.0 - rhs.0) / PAGE_SIZE
+    }
+}
+
+//4KiB frames nothing else uses, not zeroed
+pub trait FrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame>;
+}
@@ -0,0 +1,113 @@
+//everything that depends on the cpu lives behind this, the rest of the bootloader only talks to the
+//firmware
+use crate::error::BootError;
+use crate::frame_allocator::AndyFrameAllocator;
+
+mod addr;
+pub use addr::{
+    align_down, align_up, FrameAllocator, Page, PhysAddr, PhysFrame, VirtAddr, PAGE_SIZE,
+};
+
+#[cfg(target_arch = "riscv64")]
+#[path = "riscv64/mod.rs"]
+pub mod special;
+
+#[cfg(target_arch = "x86_64")]
+#[path = "x86_64/mod.rs"]
+pub mod special;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MapSize {
+    Small, //4KiB
+    Large, //2MiB
+    Huge,  //1GiB
+}
+
+impl MapSize {
+    pub const fn bytes(self) -> u64 {
+        match self {
+            MapSize::Small => 0x1000,
+            MapSize::Large => 0x20_0000,
+            MapSize::Huge => 0x4000_0000,
+        }
+    }
+}
+
+//what the kernel may do with a page, everything mapped can be read
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MapFlags {
+    pub write: bool,
+    pub execute: bool,
+    //for mmio, ignored where the page table has no say in caching
+    pub uncached: bool,
+}
+
+impl MapFlags {
+    pub const READ: MapFlags = MapFlags {
+        write: false,
+        execute: false,
+        uncached: false,
+    };
+    pub const READ_WRITE: MapFlags = MapFlags {
+        write: true,
+        ..MapFlags::READ
+    };
+    pub const READ_EXECUTE: MapFlags = MapFlags {
+        execute: true,
+        ..MapFlags::READ
+    };
+}
+
+//where the thread pointer goes relative to the tls data
+#[allow(dead_code)] //each arch only ever uses one
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TlsVariant {
+    //the data starts at the thread pointer
+    One,
+    //the data ends at the thread pointer, which points at a word pointing at itself
+    Two,
+}
+
+//the kernel's page table, built while the bootloader still runs on the firmware's identity map
+pub trait KernelPageTable: Sized {
+    fn new(frame_allocator: &mut AndyFrameAllocator) -> Result<Self, BootError>;
+
+    //the top level table, what goes into cr3 or satp
+    fn root(&self) -> PhysAddr;
+
+    fn map(
+        &mut self,
+        virt: VirtAddr,
+        phys: PhysAddr,
+        size: MapSize,
+        flags: MapFlags,
+        frame_allocator: &mut AndyFrameAllocator,
+    ) -> Result<(), BootError>;
+
+    fn translate(&self, virt: VirtAddr) -> Option<(PhysAddr, MapFlags)>;
+
+    //only for pages mapped with MapSize::Small
+    fn update_flags(&mut self, virt: VirtAddr, flags: MapFlags) -> Result<(), BootError>;
+
+    //physically contiguous 4KiB pages
+    fn map_pages(
+        &mut self,
+        virt: VirtAddr,
+        phys: PhysAddr,
+        num_pages: u64,
+        flags: MapFlags,
+        frame_allocator: &mut AndyFrameAllocator,
+    ) -> Result<(), BootError> {
+        for i in 0..num_pages {
+            let offset = i * MapSize::Small.bytes();
+            self.map(
+                virt + offset,
+                phys + offset,
+                MapSize::Small,
+                flags,
+                frame_allocator,
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,96 @@
+	/* pe/coff header for the firmware, objcopy -O binary keeps file offsets equal to addresses */
+	.section .text.head, "a"
+	.global ImageBase
+ImageBase:
+	.ascii "MZ"
+	.skip 58
+	.long pe_header - ImageBase	/* e_lfanew */
+
+pe_header:
+	.ascii "PE"
+	.short 0
+coff_header:
+	.short 0x5064			/* riscv64 */
+	.short 2			/* number of sections */
+	.long 0				/* time date stamp */
+	.long 0				/* pointer to symbol table */
+	.long 0				/* number of symbols */
+	.short section_table - optional_header
+	.short 0x0206			/* executable, no line numbers, no debug info */
+
+optional_header:
+	.short 0x020b			/* pe32+ */
+	.byte 0x02, 0x14		/* linker version */
+	.long _etext - _text		/* size of code */
+	.long _edata - _data		/* size of initialized data */
+	.long 0				/* size of uninitialized data */
+	.long _start - ImageBase	/* entry point */
+	.long _text - ImageBase		/* base of code */
+
+	.quad 0				/* image base, _start relocates the image itself */
+	.long 0x1000			/* section alignment */
+	.long 0x1000			/* file alignment */
+	.short 0, 0			/* os version */
+	.short 0, 0			/* image version */
+	.short 0, 0			/* subsystem version */
+	.long 0				/* win32 version */
+	.long _end - ImageBase		/* size of image */
+	.long _text - ImageBase		/* size of headers */
+	.long 0				/* checksum */
+	.short 10			/* efi application */
+	.short 0			/* dll characteristics */
+	.quad 0, 0, 0, 0		/* stack and heap reserve and commit */
+	.long 0				/* loader flags */
+	.long 6				/* number of data directories */
+	.quad 0, 0, 0, 0, 0, 0		/* export, import, resource, exception, certificate, base relocation */
+
+section_table:
+	.ascii ".text\0\0\0"
+	.long _etext - _text		/* virtual size */
+	.long _text - ImageBase		/* virtual address */
+	.long _etext - _text		/* size of raw data */
+	.long _text - ImageBase		/* pointer to raw data */
+	.long 0, 0			/* relocations, line numbers */
+	.short 0, 0
+	.long 0xe0000020		/* code, execute, read, write: _start relocates it in place */
+
+	.ascii ".data\0\0\0"
+	.long _end - _data		/* the bss is the rest of the virtual size */
+	.long _data - ImageBase
+	.long _edata - _data
+	.long _data - ImageBase
+	.long 0, 0
+	.short 0, 0
+	.long 0xc0000040		/* initialized data, read, write */
+
+	/* the firmware loads the image wherever it likes and doesn't know about the ELF's own relocations */
+	.section .text._start, "ax"
+	.global _start
+_start:
+	addi sp, sp, -16
+	sd a0, 0(sp)
+	sd a1, 8(sp)
+
+	lla t0, ImageBase
+	lla t1, __rela_start
+	lla t2, __rela_end
+	li t5, 3			/* R_RISCV_RELATIVE */
+relocate:
+	bgeu t1, t2, relocate_done
+	ld t3, 8(t1)			/* r_info */
+	bne t3, t5, relocate_next
+	ld t3, 0(t1)			/* r_offset */
+	ld t4, 16(t1)			/* r_addend */
+	add t3, t3, t0
+	add t4, t4, t0
+	sd t4, 0(t3)
+relocate_next:
+	addi t1, t1, 24
+	j relocate
+relocate_done:
+	fence.i
+
+	ld a0, 0(sp)
+	ld a1, 8(sp)
+	addi sp, sp, 16
+	tail efi_main
@@ -0,0 +1,309 @@
+use super::{FrameAllocator, PhysAddr, PhysFrame, VirtAddr};
+use super::{KernelPageTable, MapFlags, MapSize, TlsVariant};
+use crate::error::BootError;
+use crate::frame_allocator::AndyFrameAllocator;
+use crate::UEFI_PHYSICAL_OFFSET;
+
+//there's no uefi target for riscv64, so the bootloader is an ELF with a PE header in front of it
+//that gets cut out with objcopy, the header and the self relocation live in here
+core::arch::global_asm!(include_str!("efi_header.asm"));
+
+pub const MACHINE: xmas_elf::header::Machine = xmas_elf::header::Machine::RISC_V;
+pub const R_NONE: u32 = 0; //R_RISCV_NONE
+pub const R_RELATIVE: u32 = 3; //R_RISCV_RELATIVE
+pub const TLS_VARIANT: TlsVariant = TlsVariant::One;
+
+//sv39 only leaves the top 256GiB for the kernel's half, so this is a lot tighter than on x86_64
+//where the kernel sees all of physical memory, up to 128GiB of it
+pub const PHYSICAL_MEMORY_OFFSET: u64 = 0xffff_ffc0_0000_0000;
+//runtime regions keep their distance from each other, the firmware's code expects that
+pub const RUNTIME_SERVICES_OFFSET: u64 = 0xffff_ffe0_0000_0000;
+//position independent kernels get put somewhere in here, clear of the physical map, boot info and stack
+pub const KASLR_WINDOW_START: u64 = 0xffff_fff0_0000_0000;
+pub const KASLR_WINDOW_SIZE: u64 = 1 << 35;
+//modules are mapped one after another from here, with an unmapped page between them
+pub const MODULES_ADDR: u64 = 0xffff_fff8_0000_0000;
+//the kernel's symbol table, for backtraces
+pub const SYMBOLS_ADDR: u64 = 0xffff_fffa_0000_0000;
+//where the kernel finds the boot info, passed to it in a0
+pub const BOOT_INFO_ADDR: u64 = 0xffff_fffc_0000_0000;
+//the boot hart's tls block, other harts get theirs from the kernel
+pub const BOOT_TLS_ADDR: u64 = 0xffff_fffd_0000_0000;
+//bottom of the page that is left unmapped under the stack, so overflowing page faults instead of eating whatever is below
+pub const STACK_GUARD_PAGE_ADDR: u64 = 0xffff_fffe_0000_0000;
+
+const PTE_V: u64 = 1 << 0;
+const PTE_R: u64 = 1 << 1;
+const PTE_W: u64 = 1 << 2;
+const PTE_X: u64 = 1 << 3;
+const PTE_A: u64 = 1 << 6;
+const PTE_D: u64 = 1 << 7;
+const PTE_PPN_SHIFT: u64 = 10;
+const PTE_PPN_MASK: u64 = ((1 << 44) - 1) << PTE_PPN_SHIFT;
+
+const SATP_MODE_SV39: u64 = 8 << 60;
+const LEVELS: usize = 3;
+
+pub struct PageTable {
+    root: PhysAddr,
+}
+
+impl PageTable {
+    //still on the firmware's identity map, so the tables can be written through their physical address
+    fn entry(table: PhysAddr, index: usize) -> *mut u64 {
+        ((UEFI_PHYSICAL_OFFSET + table.as_u64()) as *mut u64).wrapping_add(index)
+    }
+
+    //the entry for virt on the given level and the level it's on, stopping early at a leaf or an
+    //empty entry unless a table is allowed to be made for it
+    fn walk(
+        &self,
+        virt: VirtAddr,
+        level: usize,
+        mut frame_allocator: Option<&mut AndyFrameAllocator>,
+    ) -> Result<(*mut u64, usize), BootError> {
+        let mut table = self.root;
+        for current in (level + 1..LEVELS).rev() {
+            let entry = Self::entry(table, vpn(virt, current));
+            let mut pte = unsafe { entry.read() };
+            if pte & PTE_V == 0 {
+                let Some(frame_allocator) = frame_allocator.as_deref_mut() else {
+                    return Ok((entry, current));
+                };
+                let frame = new_table(frame_allocator)?;
+                pte = ((frame.as_u64() >> 12) << PTE_PPN_SHIFT) | PTE_V;
+                unsafe { entry.write(pte) };
+            } else if is_leaf(pte) {
+                return Ok((entry, current));
+            }
+            table = pte_addr(pte);
+        }
+        Ok((Self::entry(table, vpn(virt, level)), level))
+    }
+}
+
+impl KernelPageTable for PageTable {
+    fn new(frame_allocator: &mut AndyFrameAllocator) -> Result<Self, BootError> {
+        let root = new_table(frame_allocator)?;
+        crate::eprintln!("New page table at: {:?}", root);
+        Ok(PageTable { root })
+    }
+
+    fn root(&self) -> PhysAddr {
+        self.root
+    }
+
+    fn map(
+        &mut self,
+        virt: VirtAddr,
+        phys: PhysAddr,
+        size: MapSize,
+        flags: MapFlags,
+        frame_allocator: &mut AndyFrameAllocator,
+    ) -> Result<(), BootError> {
+        if !is_sv39(virt) {
+            return Err(BootError::Map {
+                addr: virt,
+                reason: "outside of sv39's address space",
+            });
+        }
+        if !virt.is_aligned(size.bytes()) || !phys.is_aligned(size.bytes()) {
+            return Err(BootError::Map {
+                addr: virt,
+                reason: "not aligned to the page size",
+            });
+        }
+
+        let level = leaf_level(size);
+        let (entry, found_level) = self.walk(virt, level, Some(frame_allocator))?;
+        if found_level != level {
+            return Err(BootError::Map {
+                addr: virt,
+                reason: "inside a huge page",
+            });
+        }
+        if unsafe { entry.read() } & PTE_V != 0 {
+            return Err(BootError::Map {
+                addr: virt,
+                reason: "already mapped",
+            });
+        }
+        unsafe { entry.write(leaf_pte(phys, flags)) };
+        Ok(())
+    }
+
+    fn translate(&self, virt: VirtAddr) -> Option<(PhysAddr, MapFlags)> {
+        if !is_sv39(virt) {
+            return None;
+        }
+        let (entry, level) = self.walk(virt, 0, None).ok()?;
+        let pte = unsafe { entry.read() };
+        if pte & PTE_V == 0 || !is_leaf(pte) {
+            return None;
+        }
+        let offset = virt.as_u64() & (level_size(level) - 1);
+        Some((pte_addr(pte) + offset, pte_flags(pte)))
+    }
+
+    fn update_flags(&mut self, virt: VirtAddr, flags: MapFlags) -> Result<(), BootError> {
+        let not_mapped = BootError::Map {
+            addr: virt,
+            reason: "not mapped with a 4KiB page",
+        };
+        if !is_sv39(virt) {
+            return Err(not_mapped);
+        }
+        let (entry, level) = self.walk(virt, 0, None)?;
+        let pte = unsafe { entry.read() };
+        if level != 0 || pte & PTE_V == 0 {
+            return Err(not_mapped);
+        }
+        unsafe { entry.write(leaf_pte(pte_addr(pte), flags)) };
+        Ok(())
+    }
+}
+
+fn new_table(frame_allocator: &mut AndyFrameAllocator) -> Result<PhysAddr, BootError> {
+    let frame: PhysFrame = frame_allocator
+        .allocate_frame()
+        .ok_or(BootError::OutOfFrames("page tables"))?;
+    let ptr = (UEFI_PHYSICAL_OFFSET + frame.start_address().as_u64()) as *mut u8;
+    unsafe { core::ptr::write_bytes(ptr, 0, 4096) };
+    Ok(frame.start_address())
+}
+
+//addresses have to be sign extended from bit 38
+fn is_sv39(virt: VirtAddr) -> bool {
+    let top = virt.as_u64() >> 38;
+    top == 0 || top == (1 << 26) - 1
+}
+
+fn vpn(virt: VirtAddr, level: usize) -> usize {
+    ((virt.as_u64() >> (12 + 9 * level)) & 0x1ff) as usize
+}
+
+fn leaf_level(size: MapSize) -> usize {
+    match size {
+        MapSize::Small => 0,
+        MapSize::Large => 1,
+        MapSize::Huge => 2,
+    }
+}
+
+fn level_size(level: usize) -> u64 {
+    1 << (12 + 9 * level)
+}
+
+fn is_leaf(pte: u64) -> bool {
+    pte & (PTE_R | PTE_W | PTE_X) != 0
+}
+
+fn pte_addr(pte: u64) -> PhysAddr {
+    PhysAddr::new(((pte & PTE_PPN_MASK) >> PTE_PPN_SHIFT) << 12)
+}
+
+//accessed and dirty are set up front, hardware is allowed to fault instead of setting them itself.
+//there's nothing for uncached without svpbmt, the pmas already make mmio uncached
+fn leaf_pte(phys: PhysAddr, flags: MapFlags) -> u64 {
+    let mut pte = ((phys.as_u64() >> 12) << PTE_PPN_SHIFT) | PTE_V | PTE_R | PTE_A | PTE_D;
+    if flags.write {
+        pte |= PTE_W;
+    }
+    if flags.execute {
+        pte |= PTE_X;
+    }
+    pte
+}
+
+fn pte_flags(pte: u64) -> MapFlags {
+    MapFlags {
+        write: pte & PTE_W != 0,
+        execute: pte & PTE_X != 0,
+        uncached: false,
+    }
+}
+
+//sv39 always has gigapages
+pub fn supports_huge_pages() -> bool {
+    true
+}
+
+//for when the firmware has no rng. the zkr seed csr is machine mode only unless the firmware opens
+//it up, so all there is is the timer
+pub fn cpu_random_u64() -> u64 {
+    crate::eprintln!("falling back to the timer");
+    let time: u64;
+    unsafe { core::arch::asm!("rdtime {}", out(reg) time) };
+    time
+}
+
+//supervisor mode already can't write read only pages, and SUM and MXR stay off
+pub fn enable_protection() {}
+
+//through the sbi console, which works before and after exiting boot services
+pub fn debug_write_byte(b: u8) {
+    const SBI_EXT_DBCN: usize = 0x4442_434e;
+    const SBI_DBCN_WRITE_BYTE: usize = 2;
+    const SBI_EXT_LEGACY_PUTCHAR: usize = 1;
+
+    let error: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") b as usize => error,
+            lateout("a1") _,
+            in("a6") SBI_DBCN_WRITE_BYTE,
+            in("a7") SBI_EXT_DBCN,
+        );
+    }
+    //older sbi implementations only have the legacy one
+    if error != 0 {
+        unsafe {
+            core::arch::asm!(
+                "ecall",
+                inlateout("a0") b as usize => _,
+                in("a7") SBI_EXT_LEGACY_PUTCHAR,
+            );
+        }
+    }
+}
+
+pub fn halt() -> ! {
+    loop {
+        unsafe { core::arch::asm!("csrci sstatus, 2", "wfi") };
+    }
+}
+
+/// # Safety
+/// the page table has to map the stack, the entry point and this function at its physical address
+pub unsafe fn context_switch(
+    page_table: PhysAddr,
+    stack_top: VirtAddr,
+    entry_point: VirtAddr,
+    boot_info: VirtAddr,
+    thread_pointer: Option<VirtAddr>,
+) -> ! {
+    let satp = SATP_MODE_SV39 | (page_table.as_u64() >> 12);
+    let thread_pointer = thread_pointer.map_or(0, |thread_pointer| thread_pointer.as_u64());
+
+    //stvec still points into the firmware, so no interrupts until the kernel sets up its own
+    unsafe {
+        core::arch::asm!(
+            r#"
+            csrci sstatus, 2
+            csrw satp, {}
+            sfence.vma
+            mv sp, {}
+            mv tp, {}
+            li s0, 0
+            jr {}
+            "#,
+            in(reg) satp,
+            in(reg) stack_top.as_u64(),
+            in(reg) thread_pointer,
+            in(reg) entry_point.as_u64(),
+            in("a0") boot_info.as_u64(),
+            options(noreturn),
+        );
+    }
+}
@@ -0,0 +1,277 @@
+use super::{FrameAllocator, KernelPageTable, MapFlags, MapSize, PhysAddr, TlsVariant, VirtAddr};
+use crate::eprintln;
+use crate::error::BootError;
+use crate::frame_allocator::AndyFrameAllocator;
+use crate::UEFI_PHYSICAL_OFFSET;
+use x86_64::structures::paging::mapper::{MapToError, TranslateResult};
+use x86_64::structures::paging::{
+    Mapper, OffsetPageTable, Page, PageSize, PageTable as X86PageTable, PageTableFlags, PhysFrame,
+    Size1GiB, Size2MiB, Size4KiB, Translate,
+};
+
+pub const MACHINE: xmas_elf::header::Machine = xmas_elf::header::Machine::X86_64;
+pub const R_NONE: u32 = 0; //R_X86_64_NONE
+pub const R_RELATIVE: u32 = 8; //R_X86_64_RELATIVE
+pub const TLS_VARIANT: TlsVariant = TlsVariant::Two;
+
+//where the kernel sees all of physical memory
+pub const PHYSICAL_MEMORY_OFFSET: u64 = 0xffff_8000_0000_0000;
+//runtime regions keep their distance from each other, the firmware's code expects that
+pub const RUNTIME_SERVICES_OFFSET: u64 = 0xffff_fd00_0000_0000;
+//position independent kernels get put somewhere in here, clear of the physical map, boot info and stack
+pub const KASLR_WINDOW_START: u64 = 0xffff_f000_0000_0000;
+pub const KASLR_WINDOW_SIZE: u64 = 1 << 39;
+//modules are mapped one after another from here, with an unmapped page between them
+pub const MODULES_ADDR: u64 = 0xffff_fe00_0000_0000;
+//the kernel's symbol table, for backtraces
+pub const SYMBOLS_ADDR: u64 = 0xffff_fe80_0000_0000;
+//where the kernel finds the boot info, passed to it in rdi
+pub const BOOT_INFO_ADDR: u64 = 0xffff_ff00_0000_0000;
+//the boot cpu's tls block, other cpus get theirs from the kernel
+pub const BOOT_TLS_ADDR: u64 = 0xffff_ff40_0000_0000;
+//bottom of the page that is left unmapped under the stack, so overflowing page faults instead of eating whatever is below
+pub const STACK_GUARD_PAGE_ADDR: u64 = 0xffff_ff80_0000_0000;
+
+pub struct PageTable {
+    inner: OffsetPageTable<'static>,
+    root: PhysFrame,
+}
+
+//the x86_64 crate's paging wants its own address and allocator types, the rest of the bootloader
+//never sees them
+struct X86FrameAllocator<'a, 'b>(&'a mut AndyFrameAllocator<'b>);
+
+unsafe impl x86_64::structures::paging::FrameAllocator<Size4KiB> for X86FrameAllocator<'_, '_> {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let frame = self.0.allocate_frame()?;
+        Some(PhysFrame::containing_address(x86_phys(
+            frame.start_address(),
+        )))
+    }
+}
+
+fn x86_phys(addr: PhysAddr) -> x86_64::PhysAddr {
+    x86_64::PhysAddr::new(addr.as_u64())
+}
+
+fn x86_virt(addr: VirtAddr) -> Result<x86_64::VirtAddr, BootError> {
+    x86_64::VirtAddr::try_new(addr.as_u64()).map_err(|_| BootError::Map {
+        addr,
+        reason: "not canonical",
+    })
+}
+
+impl PageTable {
+    fn map_to<S: PageSize + core::fmt::Debug>(
+        &mut self,
+        virt: VirtAddr,
+        phys: PhysAddr,
+        flags: MapFlags,
+        frame_allocator: &mut AndyFrameAllocator,
+    ) -> Result<(), BootError>
+    where
+        OffsetPageTable<'static>: Mapper<S>,
+    {
+        let not_aligned = BootError::Map {
+            addr: virt,
+            reason: "not aligned to the page size",
+        };
+        let page: Page<S> = Page::from_start_address(x86_virt(virt)?).map_err(|_| not_aligned)?;
+        let frame: PhysFrame<S> =
+            PhysFrame::from_start_address(x86_phys(phys)).map_err(|_| BootError::Map {
+                addr: virt,
+                reason: "frame not aligned to the page size",
+            })?;
+        let flusher = unsafe {
+            self.inner
+                .map_to(
+                    page,
+                    frame,
+                    page_table_flags(flags),
+                    &mut X86FrameAllocator(frame_allocator),
+                )
+                .map_err(|err| map_error(virt, err))?
+        };
+        flusher.ignore();
+        Ok(())
+    }
+}
+
+impl KernelPageTable for PageTable {
+    fn new(frame_allocator: &mut AndyFrameAllocator) -> Result<Self, BootError> {
+        let root = frame_allocator
+            .allocate_frame()
+            .ok_or(BootError::OutOfFrames("page tables"))?;
+        eprintln!("New page table at: {:#?}", &root);
+
+        let ptr = (UEFI_PHYSICAL_OFFSET + root.start_address().as_u64()) as *mut X86PageTable;
+        unsafe { ptr.write(X86PageTable::new()) };
+        let inner =
+            unsafe { OffsetPageTable::new(&mut *ptr, x86_64::VirtAddr::new(UEFI_PHYSICAL_OFFSET)) };
+        Ok(PageTable {
+            inner,
+            root: PhysFrame::containing_address(x86_phys(root.start_address())),
+        })
+    }
+
+    fn root(&self) -> PhysAddr {
+        PhysAddr::new(self.root.start_address().as_u64())
+    }
+
+    fn map(
+        &mut self,
+        virt: VirtAddr,
+        phys: PhysAddr,
+        size: MapSize,
+        flags: MapFlags,
+        frame_allocator: &mut AndyFrameAllocator,
+    ) -> Result<(), BootError> {
+        match size {
+            MapSize::Small => self.map_to::<Size4KiB>(virt, phys, flags, frame_allocator),
+            MapSize::Large => self.map_to::<Size2MiB>(virt, phys, flags, frame_allocator),
+            MapSize::Huge => self.map_to::<Size1GiB>(virt, phys, flags, frame_allocator),
+        }
+    }
+
+    fn translate(&self, virt: VirtAddr) -> Option<(PhysAddr, MapFlags)> {
+        match self.inner.translate(x86_virt(virt).ok()?) {
+            TranslateResult::Mapped {
+                frame,
+                offset,
+                flags,
+            } => Some((
+                PhysAddr::new(frame.start_address().as_u64() + offset),
+                map_flags(flags),
+            )),
+            _ => None,
+        }
+    }
+
+    fn update_flags(&mut self, virt: VirtAddr, flags: MapFlags) -> Result<(), BootError> {
+        let page: Page<Size4KiB> = Page::containing_address(x86_virt(virt)?);
+        unsafe {
+            self.inner
+                .update_flags(page, page_table_flags(flags))
+                .map_err(|_| BootError::Map {
+                    addr: virt,
+                    reason: "not mapped with a 4KiB page",
+                })?
+                .ignore();
+        }
+        Ok(())
+    }
+}
+
+fn page_table_flags(flags: MapFlags) -> PageTableFlags {
+    let mut out = PageTableFlags::PRESENT;
+    if flags.write {
+        out |= PageTableFlags::WRITABLE;
+    }
+    if !flags.execute {
+        out |= PageTableFlags::NO_EXECUTE;
+    }
+    if flags.uncached {
+        out |= PageTableFlags::NO_CACHE;
+    }
+    out
+}
+
+fn map_flags(flags: PageTableFlags) -> MapFlags {
+    MapFlags {
+        write: flags.contains(PageTableFlags::WRITABLE),
+        execute: !flags.contains(PageTableFlags::NO_EXECUTE),
+        uncached: flags.contains(PageTableFlags::NO_CACHE),
+    }
+}
+
+//running out of frames here means running out for the page tables themselves
+fn map_error<S: PageSize>(addr: VirtAddr, err: MapToError<S>) -> BootError {
+    match err {
+        MapToError::FrameAllocationFailed => BootError::OutOfFrames("page tables"),
+        MapToError::ParentEntryHugePage => BootError::Map {
+            addr,
+            reason: "inside a huge page",
+        },
+        MapToError::PageAlreadyMapped(_) => BootError::Map {
+            addr,
+            reason: "already mapped",
+        },
+    }
+}
+
+pub fn supports_huge_pages() -> bool {
+    //cpuid 0x80000001 edx bit 26 is pdpe1gb
+    let max_extended = core::arch::x86_64::__cpuid(0x8000_0000).eax;
+    if max_extended < 0x8000_0001 {
+        return false;
+    }
+    let edx = core::arch::x86_64::__cpuid(0x8000_0001).edx;
+    edx & (1 << 26) != 0
+}
+
+//for when the firmware has no rng
+pub fn cpu_random_u64() -> u64 {
+    if let Some(random) = x86_64::instructions::random::RdRand::new().and_then(|r| r.get_u64()) {
+        return random;
+    }
+    eprintln!("no RDRAND, falling back to TSC");
+
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+//after exiting boot services, before building the kernel's page table
+pub fn enable_protection() {
+    use x86_64::registers::control::{Cr0, Cr0Flags, Efer, EferFlags};
+
+    eprintln!("enabling write protection on ring 0");
+    unsafe { Cr0::update(|cr0| *cr0 |= Cr0Flags::WRITE_PROTECT) };
+    eprintln!("enabling no execute flag");
+    unsafe { Efer::update(|efer| *efer |= EferFlags::NO_EXECUTE_ENABLE) }
+}
+
+pub fn debug_write_byte(b: u8) {
+    unsafe {
+        let port: u16 = 0xe9; //qemu -debugcon
+        core::arch::asm!("outb %al, %dx", in("al") b, in("dx") port, options(att_syntax));
+    };
+}
+
+pub fn halt() -> ! {
+    loop {
+        unsafe { core::arch::asm!("cli; hlt") };
+    }
+}
+
+/// # Safety
+/// the page table has to map the stack, the entry point and this function at its physical address
+pub unsafe fn context_switch(
+    page_table: PhysAddr,
+    stack_top: VirtAddr,
+    entry_point: VirtAddr,
+    boot_info: VirtAddr,
+    thread_pointer: Option<VirtAddr>,
+) -> ! {
+    //#[thread_local] accesses in the kernel go through fs
+    if let Some(thread_pointer) = thread_pointer {
+        x86_64::registers::model_specific::FsBase::write(x86_64::VirtAddr::new(
+            thread_pointer.as_u64(),
+        ));
+    }
+
+    unsafe {
+        core::arch::asm!(
+            r#"
+            xor rbp, rbp
+            mov cr3, {}
+            mov rsp, {}
+            jmp {}
+            "#,
+            in(reg) page_table.as_u64(),
+            in(reg) stack_top.as_u64(),
+            in(reg) entry_point.as_u64(),
+            in("rdi") boot_info.as_u64(),
+        );
+    }
+
+    unreachable!()
+}
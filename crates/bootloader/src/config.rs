@@ -0,0 +1,221 @@
+use crate::eprintln;
+use crate::read_file::{self, FileSource};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU8, Ordering};
+use uefi::prelude::*;
+use uefi::table::boot::MemoryType;
+
+//  timeout = 5
+//  default = 0
+//  stack_pages = 100
+//
+//  [andy os]
+//  kernel = efi\kernel\kernel
+//  initrd = efi\kernel\initrd
+//  cmdline = root=/dev/ram0
+//  load_options = no
+//  verbosity = normal
+//  source = disk
+//
+//keys before the first [entry] are global, blank lines and lines starting with # are skipped.
+//source is disk unless an entry asks for tftp, or auto for tftp only when a file isn't on disk.
+//the config itself only ever comes from disk, nothing goes over the network without being asked.
+//load_options = yes lets whoever started the bootloader replace cmdline, off by default because the
+//firmware's load options are often not a command line at all
+const CONFIG_PATH: &str = "efi\\kernel\\boot.cfg";
+
+const DEFAULT_KERNEL_PATH: &str = "efi\\kernel\\kernel";
+const DEFAULT_INITRD_PATH: &str = "efi\\kernel\\initrd";
+const DEFAULT_MODULES_DIR: &str = "efi\\kernel\\modules";
+const DEFAULT_STACK_PAGES: u64 = 100;
+const DEFAULT_TIMEOUT_SECS: u64 = 5;
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    //only errors
+    Quiet,
+    Normal,
+    //also dumps the config and the memory map
+    Verbose,
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+pub fn verbosity() -> Verbosity {
+    match VERBOSITY.load(Ordering::Relaxed) {
+        0 => Verbosity::Quiet,
+        1 => Verbosity::Normal,
+        _ => Verbosity::Verbose,
+    }
+}
+
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+#[derive(Debug)]
+pub struct BootEntry {
+    pub name: String,
+    pub kernel: String,
+    pub initrd: String,
+    pub modules: String,
+    pub cmdline: String,
+    //take the command line from the load options instead, when there are any
+    pub load_options: bool,
+    pub verbosity: Verbosity,
+    //where the kernel, initrd and their signatures come from, disk unless the config says otherwise
+    pub source: FileSource,
+}
+
+impl BootEntry {
+    fn new(name: &str) -> Self {
+        BootEntry {
+            name: name.to_string(),
+            kernel: DEFAULT_KERNEL_PATH.to_string(),
+            initrd: DEFAULT_INITRD_PATH.to_string(),
+            modules: DEFAULT_MODULES_DIR.to_string(),
+            cmdline: String::new(),
+            load_options: false,
+            verbosity: Verbosity::Normal,
+            source: FileSource::Disk,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct BootConfig {
+    pub timeout_secs: u64,
+    pub default_entry: usize,
+    pub stack_pages: u64,
+    //never empty
+    pub entries: Vec<BootEntry>,
+}
+
+//a missing config boots the default paths, a broken line gets skipped with a warning
+pub fn load_config(image: Handle, st: &SystemTable<Boot>) -> BootConfig {
+    let file = match read_file::load_file(
+        CONFIG_PATH,
+        FileSource::Disk,
+        MemoryType::LOADER_DATA,
+        image,
+        st,
+    ) {
+        Ok(file) => file,
+        Err(err) => {
+            if !err.is_not_found() {
+                eprintln!("{}, using defaults", err);
+            }
+            return parse_config("");
+        }
+    };
+
+    let config = match core::str::from_utf8(file) {
+        Ok(text) => parse_config(text),
+        Err(_) => {
+            eprintln!("{} is not utf-8, using defaults", CONFIG_PATH);
+            parse_config("")
+        }
+    };
+    if let Err(err) = read_file::free_file(file, st) {
+        eprintln!("{}", err);
+    }
+    config
+}
+
+fn parse_config(text: &str) -> BootConfig {
+    let mut config = BootConfig {
+        timeout_secs: DEFAULT_TIMEOUT_SECS,
+        default_entry: 0,
+        stack_pages: DEFAULT_STACK_PAGES,
+        entries: Vec::new(),
+    };
+
+    for (i, line) in text.lines().enumerate() {
+        let line_num = i + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            config.entries.push(BootEntry::new(name.trim()));
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            eprintln!("{}:{}: expected key = value", CONFIG_PATH, line_num);
+            continue;
+        };
+        let (key, value) = (key.trim(), value.trim());
+
+        let result = match config.entries.last_mut() {
+            None => parse_global(&mut config, key, value),
+            Some(entry) => parse_entry(entry, key, value),
+        };
+        if let Err(err) = result {
+            eprintln!("{}:{}: {}", CONFIG_PATH, line_num, err);
+        }
+    }
+
+    if config.entries.is_empty() {
+        config.entries.push(BootEntry::new("default"));
+    }
+    if config.default_entry >= config.entries.len() {
+        eprintln!("default entry {} doesn't exist", config.default_entry);
+        config.default_entry = 0;
+    }
+
+    config
+}
+
+fn parse_global(config: &mut BootConfig, key: &str, value: &str) -> Result<(), &'static str> {
+    match key {
+        "timeout" => config.timeout_secs = value.parse().map_err(|_| "bad timeout")?,
+        "default" => config.default_entry = value.parse().map_err(|_| "bad default entry")?,
+        "stack_pages" => {
+            config.stack_pages = value.parse().map_err(|_| "bad stack_pages")?;
+            if config.stack_pages == 0 {
+                config.stack_pages = DEFAULT_STACK_PAGES;
+                return Err("stack_pages can't be 0");
+            }
+        }
+        _ => return Err("unknown global key"),
+    }
+    Ok(())
+}
+
+fn parse_entry(entry: &mut BootEntry, key: &str, value: &str) -> Result<(), &'static str> {
+    match key {
+        "kernel" => entry.kernel = value.to_string(),
+        "initrd" => entry.initrd = value.to_string(),
+        "modules" => entry.modules = value.to_string(),
+        "cmdline" => entry.cmdline = value.to_string(),
+        "load_options" => {
+            entry.load_options = match value {
+                "yes" => true,
+                "no" => false,
+                _ => return Err("load_options has to be yes or no"),
+            }
+        }
+        "verbosity" => {
+            entry.verbosity = match value {
+                "quiet" => Verbosity::Quiet,
+                "normal" => Verbosity::Normal,
+                "verbose" => Verbosity::Verbose,
+                _ => return Err("verbosity has to be quiet, normal or verbose"),
+            }
+        }
+        "source" => {
+            entry.source = match value {
+                "disk" => FileSource::Disk,
+                "tftp" => FileSource::Tftp,
+                "auto" => FileSource::DiskThenTftp,
+                _ => return Err("source has to be disk, tftp or auto"),
+            }
+        }
+        _ => return Err("unknown entry key"),
+    }
+    Ok(())
+}
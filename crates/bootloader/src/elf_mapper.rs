@@ -1,245 +1,661 @@
+use crate::arch::special::{PageTable, R_NONE, R_RELATIVE};
+use crate::arch::{KernelPageTable, MapFlags, MapSize, PhysAddr, VirtAddr, PAGE_SIZE};
 use crate::eprintln;
+use crate::error::BootError;
 use crate::frame_allocator::AndyFrameAllocator;
 use crate::UEFI_PHYSICAL_OFFSET;
-use x86_64::structures::paging::Mapper;
-use x86_64::structures::paging::PageSize;
-use x86_64::structures::paging::Translate;
-use x86_64::{
-    structures::paging::{
-        FrameAllocator, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame, Size4KiB,
-    },
-    PhysAddr, VirtAddr,
-};
+use alloc::vec::Vec;
+use boot_info::TlsTemplate;
+use uefi::prelude::*;
+use uefi::table::boot::MemoryType;
 
-pub fn map_elf_into_memory(
-    kernel_offset: PhysAddr,
+//not in xmas_elf
+const PT_GNU_STACK: u32 = 0x6474e551;
+const RELA_ENTRY_SIZE: u64 = 24;
+
+//always map at least the 32 bit space, the local apic and ioapic live up there and aren't in the memory map
+const MIN_PHYSICAL_MAP_SIZE: u64 = 4 * MapSize::Huge.bytes();
+
+//so the kernel's own memory shows up separately in the memory map after exiting boot services
+pub const KERNEL_MEMORY_TYPE: MemoryType = MemoryType::custom(0x8000_0000);
+
+//a load segment copied out of the file into its own pages
+pub struct LoadedSegment {
+    virt_start: VirtAddr,
+    phys_start: PhysAddr,
+    num_pages: u64,
+    flags: MapFlags,
+}
+
+impl LoadedSegment {
+    fn virt_end(&self) -> VirtAddr {
+        self.virt_start + self.num_pages * PAGE_SIZE
+    }
+}
+
+//everything needed from the kernel file, so the file itself can be freed before exiting boot services
+pub struct LoadedKernel {
+    pub entry_point: VirtAddr,
+    pub stack_executable: bool,
+    pub tls: Option<TlsTemplate>,
+    segments: &'static [LoadedSegment],
+    relro: &'static [(VirtAddr, VirtAddr)],
+}
+
+//everything that has to hold before anything else looks at the file
+pub fn check_kernel_header(kernel_file: &xmas_elf::ElfFile) -> Result<(), BootError> {
+    use xmas_elf::header::{Class, Type};
+
+    if kernel_file.header.pt1.class() != Class::SixtyFour {
+        return Err(BootError::NotElf64);
+    }
+    let machine = kernel_file.header.pt2.machine().as_machine();
+    if machine != crate::arch::special::MACHINE {
+        return Err(BootError::WrongMachine(machine));
+    }
+    match kernel_file.header.pt2.type_().as_type() {
+        Type::Executable | Type::SharedObject => {}
+        other => return Err(BootError::NotExecutable(other)),
+    }
+    Ok(())
+}
+
+pub fn load_elf(
     kernel_file: &xmas_elf::ElfFile,
-    frame_allocator: &mut AndyFrameAllocator,
-) -> (OffsetPageTable<'static>, PhysFrame) {
-    assert!(PhysAddr::new(kernel_file.input as *const [u8] as *const u8 as u64) == kernel_offset);
-
-    let (mut kernel_page_table, kernel_page_table_top_frame) = {
-        let table_top_frame: PhysFrame =
-            frame_allocator.allocate_frame().expect("no unused frames");
-        eprintln!("New page table at: {:#?}", &table_top_frame);
-        let frame_addr = uefi_get_addr_of_frame(table_top_frame);
-
-        let ptr = frame_addr.as_mut_ptr();
-        unsafe { *ptr = PageTable::new() };
-        let top_table = unsafe { &mut *ptr };
-        (
-            unsafe { OffsetPageTable::new(top_table, VirtAddr::new(UEFI_PHYSICAL_OFFSET)) },
-            table_top_frame,
-        )
-    };
+    kernel_slide: u64,
+    st: &SystemTable<Boot>,
+) -> Result<LoadedKernel, BootError> {
+    let mut segments = Vec::new();
+    let mut relro = Vec::new();
+    let mut tls = None;
 
     for program_header in kernel_file.program_iter() {
         if !matches!(program_header, xmas_elf::program::ProgramHeader::Ph64(_)) {
-            panic!("only supports 64 bit elfs");
+            return Err(BootError::NotElf64);
         }
 
-        if program_header.align() != 0 {
-            assert!(
-                (program_header.virtual_addr() % program_header.align())
-                    == (program_header.offset() % program_header.align())
-            );
+        if program_header.align() != 0
+            && (program_header.virtual_addr() % program_header.align())
+                != (program_header.offset() % program_header.align())
+        {
+            return Err(BootError::Elf(
+                "segment address and file offset aren't aligned the same",
+            ));
         }
 
-        match program_header.get_type().unwrap() {
+        match program_header.get_type().map_err(BootError::Elf)? {
             xmas_elf::program::Type::Load => {
-                handle_load_segment(
-                    kernel_offset,
-                    kernel_file,
-                    &mut kernel_page_table,
-                    frame_allocator,
-                    program_header,
-                );
+                if let Some(segment) = load_segment(kernel_file, program_header, kernel_slide, st)?
+                {
+                    segments.push(segment);
+                }
             }
             xmas_elf::program::Type::GnuRelro => {
-                let make_read_only_start = program_header.virtual_addr();
-                let make_read_only_end = make_read_only_start + program_header.mem_size();
-                eprintln!("todo handle GNU_RELRO");
-                eprintln!(
-                    "should set memory {:x} to {:x} as read only memory",
-                    make_read_only_start, make_read_only_end
-                );
+                let relro_start = VirtAddr::new(program_header.virtual_addr() + kernel_slide);
+                relro.push((relro_start, relro_start + program_header.mem_size()));
+            }
+            xmas_elf::program::Type::OsSpecific(PT_GNU_STACK) => {
+                //make_stack reads this through stack_is_executable
             }
-            xmas_elf::program::Type::OsSpecific(num) => {
-                if num == 1685382481 {
-                    eprintln!("todo handle GNU_STACK");
-                } else {
-                    todo!("wierd os specific type: {:?}", num)
+            xmas_elf::program::Type::Null
+            | xmas_elf::program::Type::Note
+            | xmas_elf::program::Type::Phdr
+            | xmas_elf::program::Type::Dynamic => {
+                //nothing to map, the data is already inside a load segment if it's needed at all
+                //relocations from the dynamic segment are applied below
+            }
+            xmas_elf::program::Type::Tls => {
+                //the template itself is inside a load segment, this only says where
+                if tls.is_some() {
+                    return Err(BootError::Elf("more than one TLS segment"));
                 }
+                tls = Some(TlsTemplate {
+                    start_addr: program_header.virtual_addr() + kernel_slide,
+                    file_size: program_header.file_size(),
+                    mem_size: program_header.mem_size(),
+                    align: program_header.align().max(1),
+                });
             }
             other => {
-                todo!("wierd type: {:?}", other)
+                eprintln!("ignoring program header of type {:?}", other);
+            }
+        }
+    }
+
+    //each segment has its own frames with its own flags, so two can't share a page
+    for (i, a) in segments.iter().enumerate() {
+        for b in &segments[i + 1..] {
+            if a.virt_start < b.virt_end() && b.virt_start < a.virt_end() {
+                return Err(BootError::SegmentsOverlap(a.virt_start, b.virt_start));
             }
         }
     }
 
-    return (kernel_page_table, kernel_page_table_top_frame);
+    apply_relocations(kernel_file, &segments, kernel_slide)?;
+
+    Ok(LoadedKernel {
+        entry_point: VirtAddr::new(kernel_file.header.pt2.entry_point() + kernel_slide),
+        stack_executable: stack_is_executable(kernel_file),
+        tls,
+        segments: segments.leak(),
+        relro: relro.leak(),
+    })
 }
 
-fn handle_load_segment(
-    kernel_addr: PhysAddr,
-    kernel_file: &xmas_elf::ElfFile,
-    kernel_page_table: &mut OffsetPageTable,
+pub fn map_elf_into_memory(
+    kernel: &LoadedKernel,
     frame_allocator: &mut AndyFrameAllocator,
-    segment: xmas_elf::program::ProgramHeader,
-) {
-    assert!(matches!(
-        segment.get_type().unwrap(),
-        xmas_elf::program::Type::Load
-    ));
+    physical_memory_offset: VirtAddr,
+    max_physical_addr: PhysAddr,
+) -> Result<PageTable, BootError> {
+    let mut kernel_page_table = PageTable::new(frame_allocator)?;
 
-    let data = match segment.get_data(kernel_file).unwrap() {
+    map_physical_memory(
+        &mut kernel_page_table,
+        frame_allocator,
+        kernel,
+        physical_memory_offset,
+        max_physical_addr,
+    )?;
+
+    for segment in kernel.segments {
+        map_segment(
+            &mut kernel_page_table,
+            frame_allocator,
+            segment,
+            kernel.relro,
+        )?;
+    }
+
+    for &(relro_start, relro_end) in kernel.relro {
+        handle_relro_segment(&mut kernel_page_table, relro_start, relro_end)?;
+    }
+
+    Ok(kernel_page_table)
+}
+
+//no GNU_STACK means the old default of an executable stack, but the kernel never wants that
+fn stack_is_executable(kernel_file: &xmas_elf::ElfFile) -> bool {
+    kernel_file.program_iter().any(|program_header| {
+        matches!(
+            program_header.get_type(),
+            Ok(xmas_elf::program::Type::OsSpecific(PT_GNU_STACK))
+        ) && program_header.flags().is_execute()
+    })
+}
+
+//relocations are already applied by the time this runs, so the range can go read only
+fn handle_relro_segment(
+    kernel_page_table: &mut PageTable,
+    relro_start: VirtAddr,
+    relro_end: VirtAddr,
+) -> Result<(), BootError> {
+    //same as ld.so, a partial page at the end stays writable
+    let start = relro_start.align_down(PAGE_SIZE);
+    let end = relro_end.align_down(PAGE_SIZE);
+
+    eprintln!(
+        "making RELRO memory {:?} to {:?} read only",
+        relro_start, relro_end
+    );
+
+    let mut page = start;
+    while page < end {
+        let (_, flags) = kernel_page_table.translate(page).ok_or(BootError::Map {
+            addr: page,
+            reason: "RELRO outside of the load segments",
+        })?;
+        //map_segment keeps RELRO on 4KiB pages
+        kernel_page_table.update_flags(
+            page,
+            MapFlags {
+                write: false,
+                ..flags
+            },
+        )?;
+        page += PAGE_SIZE;
+    }
+
+    Ok(())
+}
+
+fn load_segment(
+    kernel_file: &xmas_elf::ElfFile,
+    segment: xmas_elf::program::ProgramHeader,
+    kernel_slide: u64,
+    st: &SystemTable<Boot>,
+) -> Result<Option<LoadedSegment>, BootError> {
+    let data = match segment.get_data(kernel_file).map_err(BootError::Elf)? {
         xmas_elf::program::SegmentData::Undefined(slice) => slice,
-        _ => todo!(),
+        _ => return Err(BootError::Elf("load segment has typed data")),
     };
 
-    {
-        let elf_offset = PhysAddr::new(kernel_file.input as *const [u8] as *const u8 as u64);
-        let segment_data_start = PhysAddr::new(data as *const [u8] as *const u8 as u64);
-        let segment_offset = segment.offset();
-        assert!(kernel_addr == elf_offset);
-        assert!(kernel_addr + segment_offset == segment_data_start);
+    //If the segment's memory size p_memsz is larger than the file size p_filesz, the "extra" bytes are defined to hold the value 0 and to follow the segment's initialized area. The file size may not be larger than the memory size.
+    if segment.file_size() > segment.mem_size() {
+        return Err(BootError::Elf(
+            "load segment is bigger in the file than in memory",
+        ));
+    }
+    if segment.mem_size() == 0 {
+        return Ok(None);
     }
 
-    let segment_start = kernel_addr + segment.offset();
-    let segment_end = segment_start + segment.file_size();
-
-    let segment_start_frame: PhysFrame = PhysFrame::containing_address(segment_start);
-    let segment_end_frame: PhysFrame = PhysFrame::containing_address(segment_end - 1);
+    let target_start = VirtAddr::new(segment.virtual_addr() + kernel_slide);
+    let target_end = target_start + segment.mem_size();
+    let virt_start = target_start.align_down(PAGE_SIZE);
+    let num_pages = (target_end.align_up(PAGE_SIZE) - virt_start) / PAGE_SIZE;
 
-    let target_start = VirtAddr::new(segment.virtual_addr());
-    let target_start_page: Page = Page::containing_address(target_start);
+    let phys_start = allocate_segment_frames(virt_start, num_pages, st)?;
 
     eprintln!(
-        "loading segment in real memory in memory [{:?}..{:?}] to starting at page {:?}",
-        segment_start, segment_end, target_start_page
+        "copying segment [{:?}..{:?}] into {} pages at {:?}",
+        target_start, target_end, num_pages, phys_start
     );
 
-    let mut segment_flags = PageTableFlags::PRESENT;
-    if !segment.flags().is_execute() {
-        segment_flags |= PageTableFlags::NO_EXECUTE;
+    //zero everything first, that covers the bss and the bytes around a segment that doesn't start on a page
+    let dest = uefi_get_addr(phys_start).as_mut_ptr::<u8>();
+    unsafe {
+        core::ptr::write_bytes(dest, 0, (num_pages * PAGE_SIZE) as usize);
+        core::ptr::copy_nonoverlapping(
+            data.as_ptr(),
+            dest.add((target_start - virt_start) as usize),
+            data.len(),
+        );
     }
-    if segment.flags().is_write() {
-        segment_flags |= PageTableFlags::WRITABLE;
+
+    let flags = MapFlags {
+        write: segment.flags().is_write(),
+        execute: segment.flags().is_execute(),
+        uncached: false,
+    };
+
+    Ok(Some(LoadedSegment {
+        virt_start,
+        phys_start,
+        num_pages,
+        flags,
+    }))
+}
+
+//segments with a whole 2MiB page in them get frames with the same offset into a 2MiB page as their
+//virtual address, so map_segment can use huge pages for the middle
+fn allocate_segment_frames(
+    virt_start: VirtAddr,
+    num_pages: u64,
+    st: &SystemTable<Boot>,
+) -> Result<PhysAddr, BootError> {
+    let bs = st.boot_services();
+    let virt_end = virt_start + num_pages * PAGE_SIZE;
+    let has_huge_page =
+        virt_start.align_up(MapSize::Large.bytes()) + MapSize::Large.bytes() <= virt_end;
+    if !has_huge_page {
+        let start = bs
+            .allocate_pages(
+                uefi::table::boot::AllocateType::AnyPages,
+                KERNEL_MEMORY_TYPE,
+                num_pages as usize,
+            )
+            .map_err(|_| BootError::OutOfFrames("kernel segments"))?;
+        return Ok(PhysAddr::new(start));
     }
 
-    for from_frame in PhysFrame::range_inclusive(segment_start_frame, segment_end_frame) {
-        let offset = from_frame - segment_start_frame;
-        let target_page = target_start_page + offset;
-        eprintln!("mapping frame {:?} to page {:?}", from_frame, target_page);
+    //over allocate by a 2MiB page and give back what's left over on either side
+    let pages_per_huge_page = MapSize::Large.bytes() / PAGE_SIZE;
+    let alloc_start = bs
+        .allocate_pages(
+            uefi::table::boot::AllocateType::AnyPages,
+            KERNEL_MEMORY_TYPE,
+            (num_pages + pages_per_huge_page - 1) as usize,
+        )
+        .map_err(|_| BootError::OutOfFrames("kernel segments"))?;
+    let head = virt_start.as_u64().wrapping_sub(alloc_start) % MapSize::Large.bytes();
+    let head_pages = head / PAGE_SIZE;
+    let tail_pages = pages_per_huge_page - 1 - head_pages;
 
-        match unsafe {
-            kernel_page_table.map_to(target_page, from_frame, segment_flags, frame_allocator)
-        } {
-            Ok(flusher) => {
-                flusher.ignore();
-            }
-            Err(mapping_error) => {
-                if let x86_64::structures::paging::mapper::MapToError::PageAlreadyMapped(
-                    already_there_frame,
-                ) = mapping_error
-                {
-                    assert!(already_there_frame == from_frame);
-                } else {
-                    panic!("error mapping thing: {:?}", mapping_error);
-                }
-            }
+    unsafe {
+        if head_pages > 0 {
+            bs.free_pages(alloc_start, head_pages as usize)
+                .map_err(BootError::firmware("freeing pages"))?;
+        }
+        if tail_pages > 0 {
+            let tail_start = alloc_start + (head_pages + num_pages) * PAGE_SIZE;
+            bs.free_pages(tail_start, tail_pages as usize)
+                .map_err(BootError::firmware("freeing pages"))?;
         }
     }
 
-    //If the segment's memory size p_memsz is larger than the file size p_filesz, the "extra" bytes are defined to hold the value 0 and to follow the segment's initialized area. The file size may not be larger than the memory size.
-    let segment_filesize = segment.file_size();
-    let segment_mem_size = segment.mem_size();
-    assert!(segment_filesize <= segment_mem_size);
-    if segment_filesize < segment_mem_size {
-        elf_fill_zeros(
-            target_start + segment_filesize,
-            target_start + segment_mem_size,
-            segment_flags,
-            kernel_page_table,
-            frame_allocator,
-        );
-    }
+    Ok(PhysAddr::new(alloc_start + head))
 }
 
-fn elf_fill_zeros(
-    start: VirtAddr,
-    end: VirtAddr,
-    segment_flags: PageTableFlags,
-    kernel_page_table: &mut OffsetPageTable,
+//2MiB pages wherever both addresses line up, 4KiB at the edges and over RELRO, which
+//handle_relro_segment changes a page at a time
+fn map_segment(
+    kernel_page_table: &mut PageTable,
     frame_allocator: &mut AndyFrameAllocator,
-) {
-    if !start.is_aligned(Size4KiB::SIZE) {
-        //duplicate last page and fill remaining bytes with 0
-        let last_page: Page<Size4KiB> = Page::containing_address(start - 1u64);
-        let (frame, flags) = match kernel_page_table.translate(last_page.start_address()) {
-            x86_64::structures::paging::mapper::TranslateResult::Mapped {
-                frame,
-                offset: _,
-                flags,
-            } => (frame, flags),
-            err => panic!("err: {:?}", err),
-        };
-        assert!(flags == segment_flags);
+    segment: &LoadedSegment,
+    relro: &[(VirtAddr, VirtAddr)],
+) -> Result<(), BootError> {
+    let size = segment.num_pages * PAGE_SIZE;
+    let mut num_huge_pages = 0;
+    let mut num_small_pages = 0;
 
-        let frame = if let x86_64::structures::paging::mapper::MappedFrame::Size4KiB(frame) = frame
-        {
-            frame
+    let mut offset = 0;
+    while offset < size {
+        let virt = segment.virt_start + offset;
+        let phys = segment.phys_start + offset;
+
+        let huge_page_fits = virt.is_aligned(MapSize::Large.bytes())
+            && phys.is_aligned(MapSize::Large.bytes())
+            && size - offset >= MapSize::Large.bytes()
+            && !relro
+                .iter()
+                .any(|&(start, end)| start < virt + MapSize::Large.bytes() && virt < end);
+
+        let size = if huge_page_fits {
+            num_huge_pages += 1;
+            MapSize::Large
         } else {
-            unreachable!()
+            num_small_pages += 1;
+            MapSize::Small
         };
-        let new_frame = frame_allocator.allocate_frame().unwrap();
-        let frame_ptr = frame.start_address().as_u64() as *const u8;
-        let new_frame_ptr = new_frame.start_address().as_u64() as *mut u8;
-        unsafe {
-            core::ptr::copy_nonoverlapping(frame_ptr, new_frame_ptr, Size4KiB::SIZE as usize);
+        kernel_page_table.map(virt, phys, size, segment.flags, frame_allocator)?;
+        offset += size.bytes();
+    }
+
+    eprintln!(
+        "mapped segment at {:?} to {:?} with {} 2MiB and {} 4KiB pages",
+        segment.virt_start, segment.phys_start, num_huge_pages, num_small_pages
+    );
+
+    Ok(())
+}
+
+//only what a static position independent executable from lld has in it
+fn apply_relocations(
+    kernel_file: &xmas_elf::ElfFile,
+    segments: &[LoadedSegment],
+    kernel_slide: u64,
+) -> Result<(), BootError> {
+    let dynamic_segment = match kernel_file
+        .program_iter()
+        .find(|ph| matches!(ph.get_type(), Ok(xmas_elf::program::Type::Dynamic)))
+    {
+        Some(segment) => segment,
+        None => return Ok(()),
+    };
+    let entries = match dynamic_segment
+        .get_data(kernel_file)
+        .map_err(BootError::Elf)?
+    {
+        xmas_elf::program::SegmentData::Dynamic64(entries) => entries,
+        _ => return Err(BootError::Relocation("dynamic segment isn't 64 bit")),
+    };
+
+    let mut rela_addr = None;
+    let mut rela_size = None;
+    let mut rela_entry_size = None;
+    for entry in entries {
+        use xmas_elf::dynamic::Tag;
+        match entry.get_tag() {
+            Ok(Tag::Null) => break,
+            Ok(Tag::Rela) => rela_addr = Some(entry.get_ptr().map_err(BootError::Elf)?),
+            Ok(Tag::RelaSize) => rela_size = Some(entry.get_val().map_err(BootError::Elf)?),
+            Ok(Tag::RelaEnt) => rela_entry_size = Some(entry.get_val().map_err(BootError::Elf)?),
+            Ok(Tag::Rel) | Ok(Tag::Relr) | Ok(Tag::JmpRel) => {
+                return Err(BootError::Relocation("only RELA relocations are supported"))
+            }
+            _ => {}
+        }
+    }
+
+    let (rela_addr, rela_size) = match (rela_addr, rela_size) {
+        (Some(addr), Some(size)) => (addr, size),
+        (None, None) => return Ok(()),
+        _ => {
+            return Err(BootError::Relocation(
+                "dynamic segment has half of the RELA info",
+            ))
+        }
+    };
+    if rela_entry_size.unwrap_or(RELA_ENTRY_SIZE) != RELA_ENTRY_SIZE {
+        return Err(BootError::Relocation("wrong RELA entry size"));
+    }
+
+    let rela_offset = vaddr_to_file_offset(kernel_file, rela_addr)? as usize;
+    let rela_table = kernel_file
+        .input
+        .get(rela_offset..rela_offset + rela_size as usize)
+        .ok_or(BootError::Relocation(
+            "RELA table is past the end of the file",
+        ))?;
+
+    eprintln!(
+        "applying {} relocations with slide {:#x}",
+        rela_size / RELA_ENTRY_SIZE,
+        kernel_slide
+    );
+
+    for entry in rela_table.chunks_exact(RELA_ENTRY_SIZE as usize) {
+        let offset = u64::from_le_bytes(entry[0..8].try_into().unwrap());
+        let info = u64::from_le_bytes(entry[8..16].try_into().unwrap());
+        let addend = i64::from_le_bytes(entry[16..24].try_into().unwrap());
+
+        match (info & 0xffff_ffff) as u32 {
+            R_NONE => {}
+            R_RELATIVE => {
+                let value = kernel_slide.wrapping_add_signed(addend);
+                write_to_segments(
+                    segments,
+                    VirtAddr::new(offset + kernel_slide),
+                    &value.to_le_bytes(),
+                )?;
+            }
+            other => return Err(BootError::UnsupportedRelocation(other)),
         }
-        kernel_page_table.unmap(last_page).unwrap().1.ignore();
+    }
+
+    Ok(())
+}
+
+fn vaddr_to_file_offset(kernel_file: &xmas_elf::ElfFile, vaddr: u64) -> Result<u64, BootError> {
+    kernel_file
+        .program_iter()
+        .filter(|ph| matches!(ph.get_type(), Ok(xmas_elf::program::Type::Load)))
+        .find(|ph| (ph.virtual_addr()..ph.virtual_addr() + ph.file_size()).contains(&vaddr))
+        .map(|ph| vaddr - ph.virtual_addr() + ph.offset())
+        .ok_or(BootError::Relocation(
+            "RELA table isn't inside any load segment",
+        ))
+}
+
+fn write_to_segments(
+    segments: &[LoadedSegment],
+    dest: VirtAddr,
+    src: &[u8],
+) -> Result<(), BootError> {
+    let dest_end = dest + src.len() as u64;
+    let segment = segments
+        .iter()
+        .find(|segment| segment.virt_start <= dest && dest_end <= segment.virt_end())
+        .ok_or(BootError::Relocation(
+            "relocation outside of the kernel's load segments",
+        ))?;
+    let phys = segment.phys_start + (dest - segment.virt_start);
+    unsafe {
+        core::ptr::copy_nonoverlapping(
+            src.as_ptr(),
+            uefi_get_addr(phys).as_mut_ptr::<u8>(),
+            src.len(),
+        );
+    }
+
+    Ok(())
+}
+
+//the kernel's pages aren't mapped in the bootloader's address space, so go through the physical frames
+pub fn write_to_kernel(kernel_page_table: &PageTable, dest: VirtAddr, src: &[u8]) {
+    let mut done = 0;
+    while done < src.len() {
+        let addr = dest + done as u64;
+        let (phys, _) = kernel_page_table
+            .translate(addr)
+            .expect("writing to unmapped kernel memory");
+        let left_in_page = (PAGE_SIZE - (addr.as_u64() % PAGE_SIZE)) as usize;
+        let chunk = core::cmp::min(left_in_page, src.len() - done);
         unsafe {
-            kernel_page_table
-                .map_to(last_page, new_frame, flags, frame_allocator)
-                .unwrap()
-                .ignore();
+            core::ptr::copy_nonoverlapping(
+                src.as_ptr().add(done),
+                uefi_get_addr(phys).as_mut_ptr::<u8>(),
+                chunk,
+            );
         }
+        done += chunk;
+    }
+}
 
-        let bytes_before_zeros = start.as_u64() % Size4KiB::SIZE;
+pub fn read_from_kernel(kernel_page_table: &PageTable, src: VirtAddr, dest: &mut [u8]) {
+    let mut done = 0;
+    while done < dest.len() {
+        let addr = src + done as u64;
+        let (phys, _) = kernel_page_table
+            .translate(addr)
+            .expect("reading from unmapped kernel memory");
+        let left_in_page = (PAGE_SIZE - (addr.as_u64() % PAGE_SIZE)) as usize;
+        let chunk = core::cmp::min(left_in_page, dest.len() - done);
         unsafe {
-            core::ptr::write_bytes(
-                new_frame_ptr.add(bytes_before_zeros as usize),
-                0,
-                (Size4KiB::SIZE - bytes_before_zeros) as usize,
+            core::ptr::copy_nonoverlapping(
+                uefi_get_addr(phys).as_ptr::<u8>(),
+                dest.as_mut_ptr().add(done),
+                chunk,
             );
         }
+        done += chunk;
     }
-    let start_page: Page = Page::containing_address(VirtAddr::new(x86_64::align_up(
-        start.as_u64(),
-        Size4KiB::SIZE,
-    )));
-    let end_page = Page::containing_address(end - 1u64);
+}
 
-    for page in Page::range_inclusive(start_page, end_page) {
-        let frame = frame_allocator.allocate_frame().unwrap();
+pub fn max_physical_address(memory_map: &uefi::table::boot::MemoryMap) -> PhysAddr {
+    let max_addr = memory_map
+        .entries()
+        .map(|descriptor| {
+            descriptor.phys_start + descriptor.page_count * (uefi::table::boot::PAGE_SIZE as u64)
+        })
+        .max()
+        .unwrap_or(0);
+    PhysAddr::new(core::cmp::max(max_addr, MIN_PHYSICAL_MAP_SIZE))
+}
 
-        let frame_ptr = frame.start_address().as_u64() as *mut [u8; Size4KiB::SIZE as usize];
-        unsafe { frame_ptr.write([0; Size4KiB::SIZE as usize]) };
+//the kernel's read only frames stay read only in here too, a writable alias would undo W^X and RELRO
+fn map_physical_memory(
+    kernel_page_table: &mut PageTable,
+    frame_allocator: &mut AndyFrameAllocator,
+    kernel: &LoadedKernel,
+    physical_memory_offset: VirtAddr,
+    max_physical_addr: PhysAddr,
+) -> Result<(), BootError> {
+    eprintln!(
+        "mapping physical memory up to {:?} at offset {:?}",
+        max_physical_addr, physical_memory_offset
+    );
+    let size = if crate::arch::special::supports_huge_pages() {
+        MapSize::Huge
+    } else {
+        MapSize::Large
+    };
+    assert!(physical_memory_offset.is_aligned(size.bytes()));
 
-        let flusher = unsafe {
-            kernel_page_table
-                .map_to(page, frame, segment_flags, frame_allocator)
-                .unwrap()
+    let end = max_physical_addr.align_up(size.bytes());
+    let mut phys = PhysAddr::new(0);
+    while phys < end {
+        map_physical_chunk(
+            kernel_page_table,
+            frame_allocator,
+            kernel,
+            physical_memory_offset,
+            phys,
+            size,
+        )?;
+        phys += size.bytes();
+    }
+
+    Ok(())
+}
+
+//big pages where the kernel isn't, splitting down to 4KiB pages around its read only frames
+fn map_physical_chunk(
+    kernel_page_table: &mut PageTable,
+    frame_allocator: &mut AndyFrameAllocator,
+    kernel: &LoadedKernel,
+    physical_memory_offset: VirtAddr,
+    phys: PhysAddr,
+    size: MapSize,
+) -> Result<(), BootError> {
+    let end = phys + size.bytes();
+    let read_only = overlaps_read_only(kernel, phys, end);
+    let smaller = match size {
+        MapSize::Huge => MapSize::Large,
+        MapSize::Large => MapSize::Small,
+        MapSize::Small => MapSize::Small,
+    };
+    if !read_only || size == MapSize::Small {
+        let flags = if read_only {
+            MapFlags::READ
+        } else {
+            MapFlags::READ_WRITE
         };
-        flusher.ignore();
+        return kernel_page_table.map(
+            physical_memory_offset + phys.as_u64(),
+            phys,
+            size,
+            flags,
+            frame_allocator,
+        );
+    }
+
+    let mut part = phys;
+    while part < end {
+        map_physical_chunk(
+            kernel_page_table,
+            frame_allocator,
+            kernel,
+            physical_memory_offset,
+            part,
+            smaller,
+        )?;
+        part += smaller.bytes();
     }
+    Ok(())
 }
 
-fn uefi_get_addr_of_frame(frame: PhysFrame) -> VirtAddr {
-    uefi_get_addr(frame.start_address())
+//frames of read only segments and the whole pages of RELRO, where handle_relro_segment drops write
+fn overlaps_read_only(kernel: &LoadedKernel, start: PhysAddr, end: PhysAddr) -> bool {
+    let overlaps =
+        |range_start: PhysAddr, range_end: PhysAddr| range_start < end && start < range_end;
+    let read_only_segments = kernel
+        .segments
+        .iter()
+        .filter(|segment| !segment.flags.write)
+        .any(|segment| {
+            overlaps(
+                segment.phys_start,
+                segment.phys_start + segment.num_pages * PAGE_SIZE,
+            )
+        });
+    let relro = kernel.relro.iter().any(|&(relro_start, relro_end)| {
+        let relro_start = relro_start.align_down(PAGE_SIZE);
+        let relro_end = relro_end.align_down(PAGE_SIZE);
+        kernel.segments.iter().any(|segment| {
+            let virt_start = relro_start.max(segment.virt_start);
+            let virt_end = relro_end.min(segment.virt_end());
+            virt_start < virt_end
+                && overlaps(
+                    segment.phys_start + (virt_start - segment.virt_start),
+                    segment.phys_start + (virt_end - segment.virt_start),
+                )
+        })
+    });
+    read_only_segments || relro
 }
 
-fn uefi_get_addr(physical_addr: PhysAddr) -> VirtAddr {
+pub fn uefi_get_addr(physical_addr: PhysAddr) -> VirtAddr {
     VirtAddr::new(physical_addr.as_u64() - UEFI_PHYSICAL_OFFSET)
 }
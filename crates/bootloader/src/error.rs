@@ -0,0 +1,143 @@
+use crate::arch::VirtAddr;
+use crate::config;
+use crate::eprintln;
+use alloc::string::String;
+use core::fmt;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
+use uefi::prelude::*;
+use uefi::table::runtime::ResetType;
+
+//cleared right before exiting boot services, after that there is no console to print to or key to wait for
+static BOOT_SERVICES_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug)]
+pub enum BootError {
+    File {
+        path: String,
+        err: uefi::Error,
+    },
+    NotAFile(String),
+    //xmas_elf only has strings for its errors
+    Elf(&'static str),
+    NotElf64,
+    WrongMachine(xmas_elf::header::Machine),
+    NotExecutable(xmas_elf::header::Type),
+    SegmentsOverlap(VirtAddr, VirtAddr),
+    Relocation(&'static str),
+    UnsupportedRelocation(u32),
+    KernelTooBig,
+    Signature {
+        path: String,
+        err: ed25519_compact::Error,
+    },
+    OutOfFrames(&'static str),
+    Map {
+        addr: VirtAddr,
+        reason: &'static str,
+    },
+    Firmware {
+        what: &'static str,
+        err: uefi::Error,
+    },
+}
+
+impl BootError {
+    pub fn file(path: &str, err: uefi::Error) -> Self {
+        BootError::File {
+            path: String::from(path),
+            err,
+        }
+    }
+
+    pub fn firmware(what: &'static str) -> impl FnOnce(uefi::Error) -> Self {
+        move |err| BootError::Firmware { what, err }
+    }
+
+    //missing files are fine in a few places, like the config and the modules
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, BootError::File { err, .. } if err.status() == Status::NOT_FOUND)
+    }
+}
+
+impl fmt::Display for BootError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BootError::File { path, err } => write!(f, "can't read {}: {:?}", path, err.status()),
+            BootError::NotAFile(path) => write!(f, "{} is a directory", path),
+            BootError::Elf(err) => write!(f, "bad kernel ELF: {}", err),
+            BootError::NotElf64 => write!(f, "kernel is not a 64 bit ELF"),
+            BootError::WrongMachine(machine) => {
+                write!(
+                    f,
+                    "kernel is built for {:?}, not {:?}",
+                    machine,
+                    crate::arch::special::MACHINE
+                )
+            }
+            BootError::NotExecutable(ty) => write!(f, "kernel is not an executable: {:?}", ty),
+            BootError::SegmentsOverlap(a, b) => {
+                write!(
+                    f,
+                    "kernel load segments at {:?} and {:?} share a page",
+                    a, b
+                )
+            }
+            BootError::Relocation(err) => write!(f, "bad kernel relocations: {}", err),
+            BootError::UnsupportedRelocation(ty) => {
+                write!(f, "unsupported relocation type {}", ty)
+            }
+            BootError::KernelTooBig => write!(f, "kernel too big for the KASLR window"),
+            BootError::Signature { path, err } => {
+                write!(f, "refusing to boot, bad signature for {}: {}", path, err)
+            }
+            BootError::OutOfFrames(what) => write!(f, "out of memory for {}", what),
+            BootError::Map { addr, reason } => {
+                write!(f, "can't map {:?} for the kernel: {}", addr, reason)
+            }
+            BootError::Firmware { what, err } => write!(f, "{} failed: {:?}", what, err.status()),
+        }
+    }
+}
+
+pub fn set_boot_services_active(active: bool) {
+    BOOT_SERVICES_ACTIVE.store(active, Ordering::SeqCst);
+}
+
+pub fn boot_services_active() -> bool {
+    BOOT_SERVICES_ACTIVE.load(Ordering::SeqCst)
+}
+
+//shows the error on screen as well as the debug port and reboots once a key is pressed
+pub fn fail_and_reboot(err: &dyn fmt::Display, st: &mut SystemTable<Boot>) -> ! {
+    //errors always get through, even when quiet
+    config::set_verbosity(config::Verbosity::Normal);
+    eprintln!("error: {}", err);
+
+    //the menu might have left the cursor anywhere, and nothing here is worth giving up over
+    let stdout = st.stdout();
+    let _ = stdout.clear();
+    let _ = write!(
+        stdout,
+        "boot failed: {}\r\n\r\npress any key to reboot\r\n",
+        err
+    );
+
+    let _ = st.stdin().reset(false);
+    if let Some(key_event) = st.stdin().wait_for_key_event() {
+        let _ = st.boot_services().wait_for_event(&mut [key_event]);
+    }
+
+    eprintln!("rebooting");
+    st.runtime_services()
+        .reset(ResetType::COLD, Status::ABORTED, None)
+}
+
+//after exiting boot services only the debug port is left, and without a keyboard rebooting right
+//away would just loop
+pub fn fail_and_halt(err: &dyn fmt::Display) -> ! {
+    config::set_verbosity(config::Verbosity::Normal);
+    eprintln!("error: {}", err);
+
+    crate::arch::special::halt()
+}
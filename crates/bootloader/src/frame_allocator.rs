@@ -1,15 +1,22 @@
-use x86_64::{
-    structures::paging::{
-        FrameAllocator, PhysFrame, Size4KiB,
-    },
-    PhysAddr
-};
+use crate::arch::{FrameAllocator, PhysAddr, PhysFrame};
 
+//can't allocate after exiting boot services so this has to be fixed size,
+//every allocation from the same descriptor merges into one range. once it's full allocating fails,
+//which callers turn into BootError::OutOfFrames, instead of losing track of frames
+pub const MAX_USED_RANGES: usize = 64;
+
+#[derive(Clone, Copy, Debug)]
+pub struct UsedRange {
+    pub start: PhysAddr,
+    pub end: PhysAddr,
+}
 
 pub struct AndyFrameAllocator<'a> {
     next_frame: PhysFrame,
     memory_map: core::iter::Copied<uefi::table::boot::MemoryMapIter<'a>>,
     curr_descriptor: Option<uefi::table::boot::MemoryDescriptor>,
+    used: [UsedRange; MAX_USED_RANGES],
+    num_used: usize,
 }
 
 impl<'a> AndyFrameAllocator<'a> {
@@ -19,12 +26,43 @@ impl<'a> AndyFrameAllocator<'a> {
             next_frame: PhysFrame::from_start_address(PhysAddr::new(0x1000)).unwrap(),
             memory_map,
             curr_descriptor: None,
+            used: [UsedRange {
+                start: PhysAddr::new(0),
+                end: PhysAddr::new(0),
+            }; MAX_USED_RANGES],
+            num_used: 0,
+        }
+    }
+
+    //every frame handed out so far, sorted and not overlapping
+    pub fn used_ranges(&self) -> &[UsedRange] {
+        &self.used[..self.num_used]
+    }
+
+    //false if there's no room left to remember it
+    fn record_used(&mut self, frame: PhysFrame) -> bool {
+        let frame_end = frame.start_address() + frame.size();
+        if let Some(last) = self.used[..self.num_used].last_mut() {
+            if last.end == frame.start_address() {
+                last.end = frame_end;
+                return true;
+            }
+        }
+        if self.num_used == MAX_USED_RANGES {
+            return false;
         }
+        self.used[self.num_used] = UsedRange {
+            start: frame.start_address(),
+            end: frame_end,
+        };
+        self.num_used += 1;
+        true
     }
+
     fn allocate_frame_from_descriptor(
         &mut self,
         descriptor: uefi::table::boot::MemoryDescriptor,
-    ) -> Option<PhysFrame<Size4KiB>> {
+    ) -> Option<PhysFrame> {
         let mem_start = PhysAddr::new(descriptor.phys_start);
         let mem_len = descriptor.page_count * (uefi::table::boot::PAGE_SIZE as u64);
         let mem_end = mem_start + mem_len;
@@ -40,15 +78,13 @@ impl<'a> AndyFrameAllocator<'a> {
         if self.next_frame <= end_frame {
             let out = self.next_frame;
             self.next_frame += 1;
-            return Some(out);
+            Some(out)
         } else {
-            return None;
+            None
         }
     }
-}
 
-unsafe impl FrameAllocator<Size4KiB> for AndyFrameAllocator<'_> {
-    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+    fn next_usable_frame(&mut self) -> Option<PhysFrame> {
         if let Some(descriptor) = self.curr_descriptor {
             if let Some(success) = self.allocate_frame_from_descriptor(descriptor) {
                 return Some(success);
@@ -71,3 +107,10 @@ unsafe impl FrameAllocator<Size4KiB> for AndyFrameAllocator<'_> {
     }
 }
 
+impl FrameAllocator for AndyFrameAllocator<'_> {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let frame = self.next_usable_frame()?;
+        self.record_used(frame).then_some(frame)
+    }
+}
+
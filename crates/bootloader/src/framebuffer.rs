@@ -0,0 +1,83 @@
+use crate::eprintln;
+use boot_info::{FrameBufferInfo, PixelFormat};
+use uefi::prelude::*;
+use uefi::proto::console::gop::{self, GraphicsOutput};
+use uefi::table::boot::{OpenProtocolAttributes, OpenProtocolParams};
+
+//firmware offers modes way bigger than this, and every extra pixel makes scrolling slower
+const MAX_WIDTH: usize = 1280;
+const MAX_HEIGHT: usize = 1024;
+
+//has to happen before exiting boot services, returns None if there is no linear framebuffer
+pub fn init_framebuffer(image: Handle, st: &SystemTable<Boot>) -> Option<FrameBufferInfo> {
+    let bs = st.boot_services();
+    let handle = bs.get_handle_for_protocol::<GraphicsOutput>().ok()?;
+
+    //not exclusive, that would disconnect the firmware console we still print to
+    let mut gop = unsafe {
+        bs.open_protocol::<GraphicsOutput>(
+            OpenProtocolParams {
+                handle,
+                agent: image,
+                controller: None,
+            },
+            OpenProtocolAttributes::GetProtocol,
+        )
+    }
+    .ok()?;
+
+    let best_mode = gop
+        .modes(bs)
+        .filter(|mode| {
+            let (width, height) = mode.info().resolution();
+            width <= MAX_WIDTH
+                && height <= MAX_HEIGHT
+                && mode.info().pixel_format() != gop::PixelFormat::BltOnly
+        })
+        .max_by_key(|mode| {
+            let (width, height) = mode.info().resolution();
+            width * height
+        });
+    if let Some(mode) = best_mode {
+        //the current mode is still fine if this fails
+        if gop.set_mode(&mode).is_err() {
+            eprintln!("failed to set graphics mode {:?}", mode.info().resolution());
+        }
+    }
+
+    let mode_info = gop.current_mode_info();
+    let pixel_format = match mode_info.pixel_format() {
+        gop::PixelFormat::Rgb => PixelFormat::Rgb,
+        gop::PixelFormat::Bgr => PixelFormat::Bgr,
+        gop::PixelFormat::Bitmask => {
+            let mask = mode_info.pixel_bitmask()?;
+            PixelFormat::Bitmask {
+                red: mask.red,
+                green: mask.green,
+                blue: mask.blue,
+            }
+        }
+        gop::PixelFormat::BltOnly => {
+            eprintln!("graphics output has no linear framebuffer");
+            return None;
+        }
+    };
+
+    let (width, height) = mode_info.resolution();
+    let mut frame_buffer = gop.frame_buffer();
+    let info = FrameBufferInfo {
+        addr: frame_buffer.as_mut_ptr() as u64,
+        size: frame_buffer.size() as u64,
+        width: width as u32,
+        height: height as u32,
+        stride: mode_info.stride() as u32,
+        pixel_format,
+    };
+
+    eprintln!(
+        "using {}x{} framebuffer at {:#x} with {:?}",
+        info.width, info.height, info.addr, info.pixel_format
+    );
+
+    Some(info)
+}
@@ -0,0 +1,317 @@
+use crate::arch::special::{PageTable, BOOT_INFO_ADDR};
+use crate::arch::{
+    align_up, FrameAllocator, KernelPageTable, MapFlags, MapSize, Page, PhysAddr, PhysFrame,
+    VirtAddr, PAGE_SIZE,
+};
+use crate::elf_mapper::{uefi_get_addr, write_to_kernel, KERNEL_MEMORY_TYPE};
+use crate::eprintln;
+use crate::error::BootError;
+use crate::frame_allocator::AndyFrameAllocator;
+use crate::modules::{LoadedModule, Modules, MODULE_MEMORY_TYPE};
+use crate::symbols::LoadedSymbols;
+use alloc::string::{String, ToString};
+use boot_info::{
+    BootInfo, FrameBufferInfo, Measurement, MemoryRegion, MemoryRegionKind, Module, Slice,
+    TlsTemplate,
+};
+use uefi::prelude::*;
+use uefi::table::boot::{MemoryMap, MemoryType};
+
+//the flattened device tree, for firmware that describes the hardware with one instead of acpi
+pub const DEVICE_TREE_GUID: uefi::Guid = uefi::guid!("b1b621d5-f19c-41a5-830b-d9152c69aae0");
+
+//everything that goes into the boot info besides the memory map
+pub struct HandoffInfo<'a> {
+    pub physical_memory_offset: VirtAddr,
+    pub kernel_slide: u64,
+    pub cmdline: &'a str,
+    pub framebuffer: Option<FrameBufferInfo>,
+    pub modules: &'a Modules,
+    pub symbols: Option<&'a LoadedSymbols>,
+    pub kernel_measurement: Measurement,
+    pub initrd_measurement: Option<Measurement>,
+    pub tls_template: Option<TlsTemplate>,
+    pub rsdp_addr: Option<PhysAddr>,
+    pub smbios_addr: Option<PhysAddr>,
+    pub device_tree_addr: Option<PhysAddr>,
+    pub runtime_services_addr: Option<VirtAddr>,
+}
+
+pub struct BootInfoRegion {
+    start: VirtAddr,
+    max_memory_regions: usize,
+    memory_regions_offset: u64,
+    modules_offset: u64,
+    cmdline_offset: u64,
+    cmdline_len: usize,
+    module_names_offset: u64,
+}
+
+impl BootInfoRegion {
+    //has to happen before the memory map is final, the frames for the boot info come out of it
+    pub fn allocate(
+        kernel_page_table: &mut PageTable,
+        frame_allocator: &mut AndyFrameAllocator,
+        max_memory_regions: usize,
+        info: &HandoffInfo,
+    ) -> Result<Self, BootError> {
+        let cmdline_len = info.cmdline.len();
+        let memory_regions_offset = align_up(
+            core::mem::size_of::<BootInfo>() as u64,
+            core::mem::align_of::<MemoryRegion>() as u64,
+        );
+        let modules_offset = align_up(
+            memory_regions_offset
+                + (max_memory_regions * core::mem::size_of::<MemoryRegion>()) as u64,
+            core::mem::align_of::<Module>() as u64,
+        );
+        //strings go last since they don't need any alignment
+        let cmdline_offset =
+            modules_offset + (info.modules.extra.len() * core::mem::size_of::<Module>()) as u64;
+        let module_names_offset = cmdline_offset + cmdline_len as u64;
+        let module_names_len: usize = info.modules.iter().map(|module| module.name.len()).sum();
+        let size = module_names_offset + module_names_len as u64;
+
+        let start = VirtAddr::new(BOOT_INFO_ADDR);
+        let start_page: Page = Page::containing_address(start);
+        let end_page: Page = Page::containing_address(start + (size - 1));
+
+        eprintln!("mapping {} bytes of boot info at {:?}", size, start_page);
+
+        for page in Page::range_inclusive(start_page, end_page) {
+            let frame: PhysFrame = frame_allocator
+                .allocate_frame()
+                .ok_or(BootError::OutOfFrames("the boot info"))?;
+            let frame_ptr = uefi_get_addr(frame.start_address()).as_mut_ptr::<u8>();
+            unsafe { core::ptr::write_bytes(frame_ptr, 0, PAGE_SIZE as usize) };
+
+            kernel_page_table.map(
+                page.start_address(),
+                frame.start_address(),
+                MapSize::Small,
+                MapFlags::READ_WRITE,
+                frame_allocator,
+            )?;
+        }
+
+        Ok(BootInfoRegion {
+            start,
+            max_memory_regions,
+            memory_regions_offset,
+            modules_offset,
+            cmdline_offset,
+            cmdline_len,
+            module_names_offset,
+        })
+    }
+
+    //has to be the last thing before jumping to the kernel, nothing can be allocated after this
+    pub fn write(
+        self,
+        kernel_page_table: &PageTable,
+        memory_map: &MemoryMap,
+        frame_allocator: &AndyFrameAllocator,
+        info: &HandoffInfo,
+    ) -> VirtAddr {
+        assert!(info.cmdline.len() == self.cmdline_len);
+
+        let memory_regions_start = self.start + self.memory_regions_offset;
+        let mut regions = MemoryRegionWriter {
+            kernel_page_table,
+            start: memory_regions_start,
+            max_regions: self.max_memory_regions,
+            num_regions: 0,
+            pending: None,
+        };
+
+        let used_ranges = frame_allocator.used_ranges();
+        for descriptor in memory_map.entries() {
+            let start = descriptor.phys_start;
+            let end = start + descriptor.page_count * (uefi::table::boot::PAGE_SIZE as u64);
+            let kind = memory_region_kind(descriptor.ty);
+
+            if kind != MemoryRegionKind::Usable {
+                regions.push(start, end, kind);
+                continue;
+            }
+
+            //frames the allocator handed out for the kernel get carved out of usable memory
+            let mut cursor = start;
+            for used in used_ranges {
+                let used_start = core::cmp::max(used.start.as_u64(), start);
+                let used_end = core::cmp::min(used.end.as_u64(), end);
+                if used_start >= used_end {
+                    continue;
+                }
+                regions.push(cursor, used_start, MemoryRegionKind::Usable);
+                regions.push(used_start, used_end, MemoryRegionKind::Bootloader);
+                cursor = used_end;
+            }
+            regions.push(cursor, end, MemoryRegionKind::Usable);
+        }
+        let num_regions = regions.finish();
+
+        let cmdline_start = self.start + self.cmdline_offset;
+        write_to_kernel(kernel_page_table, cmdline_start, info.cmdline.as_bytes());
+
+        let mut module_names_start = self.start + self.module_names_offset;
+        let mut write_module = |module: &LoadedModule| {
+            let name = module.name.as_bytes();
+            write_to_kernel(kernel_page_table, module_names_start, name);
+            let boot_module = Module {
+                name: Slice::new(module_names_start.as_ptr(), name.len() as u64),
+                start: module.virt_start.as_u64(),
+                phys_start: module.phys_start.as_u64(),
+                len: module.len,
+            };
+            module_names_start += name.len() as u64;
+            boot_module
+        };
+
+        let initrd = info.modules.initrd.as_ref().map(&mut write_module);
+        let modules_start = self.start + self.modules_offset;
+        for (i, module) in info.modules.extra.iter().enumerate() {
+            let boot_module = write_module(module);
+            let addr = modules_start + (i * core::mem::size_of::<Module>()) as u64;
+            write_to_kernel(kernel_page_table, addr, as_bytes(&boot_module));
+        }
+
+        let boot_info = BootInfo {
+            magic: boot_info::BOOT_INFO_MAGIC,
+            version: boot_info::BOOT_INFO_VERSION,
+            size: core::mem::size_of::<BootInfo>() as u32,
+            physical_memory_offset: info.physical_memory_offset.as_u64(),
+            kernel_slide: info.kernel_slide,
+            memory_regions: Slice::new(memory_regions_start.as_ptr(), num_regions as u64),
+            cmdline: Slice::new(cmdline_start.as_ptr(), info.cmdline.len() as u64),
+            framebuffer: info.framebuffer.into(),
+            initrd: initrd.into(),
+            modules: Slice::new(modules_start.as_ptr(), info.modules.extra.len() as u64),
+            kernel_measurement: info.kernel_measurement,
+            initrd_measurement: info.initrd_measurement.into(),
+            tls_template: info.tls_template.into(),
+            rsdp_addr: info.rsdp_addr.map(PhysAddr::as_u64).into(),
+            smbios_addr: info.smbios_addr.map(PhysAddr::as_u64).into(),
+            runtime_services_addr: info.runtime_services_addr.map(VirtAddr::as_u64).into(),
+            device_tree_addr: info.device_tree_addr.map(PhysAddr::as_u64).into(),
+            symbols: match info.symbols {
+                Some(symbols) => Slice::new(symbols.symbols_addr().as_ptr(), symbols.num_symbols()),
+                None => Slice::empty(),
+            },
+            symbol_names: match info.symbols {
+                Some(symbols) => Slice::new(symbols.names_addr().as_ptr(), symbols.names_len()),
+                None => Slice::empty(),
+            },
+        };
+        write_to_kernel(kernel_page_table, self.start, as_bytes(&boot_info));
+
+        eprintln!(
+            "wrote boot info with {} memory regions and cmdline {:?}",
+            num_regions, info.cmdline
+        );
+
+        self.start
+    }
+}
+
+//merges neighbours of the same kind as it goes
+struct MemoryRegionWriter<'a> {
+    kernel_page_table: &'a PageTable,
+    start: VirtAddr,
+    max_regions: usize,
+    num_regions: usize,
+    pending: Option<MemoryRegion>,
+}
+
+impl MemoryRegionWriter<'_> {
+    fn push(&mut self, start: u64, end: u64, kind: MemoryRegionKind) {
+        if start >= end {
+            return;
+        }
+        if let Some(prev) = self.pending.as_mut() {
+            if prev.end == start && prev.kind == kind {
+                prev.end = end;
+                return;
+            }
+        }
+        self.flush();
+        self.pending = Some(MemoryRegion { start, end, kind });
+    }
+
+    fn flush(&mut self) {
+        if let Some(region) = self.pending.take() {
+            assert!(self.num_regions < self.max_regions);
+            let addr =
+                self.start + (self.num_regions * core::mem::size_of::<MemoryRegion>()) as u64;
+            write_to_kernel(self.kernel_page_table, addr, as_bytes(&region));
+            self.num_regions += 1;
+        }
+    }
+
+    fn finish(mut self) -> usize {
+        self.flush();
+        self.num_regions
+    }
+}
+
+pub fn read_cmdline(image: Handle, st: &SystemTable<Boot>) -> String {
+    let loaded_image = match st
+        .boot_services()
+        .open_protocol_exclusive::<uefi::proto::loaded_image::LoadedImage>(image)
+    {
+        Ok(loaded_image) => loaded_image,
+        Err(_) => return String::new(),
+    };
+
+    match loaded_image.load_options_as_cstr16() {
+        Ok(options) => skip_program_name(&options.to_string()).to_string(),
+        Err(_) => String::new(),
+    }
+}
+
+//the shell passes the whole command line, the bootloader's own path included
+fn skip_program_name(options: &str) -> &str {
+    let options = options.trim_start();
+    let rest = match options.strip_prefix('"') {
+        Some(quoted) => quoted.split_once('"').map_or("", |(_, rest)| rest),
+        None => options
+            .split_once(char::is_whitespace)
+            .map_or("", |(_, rest)| rest),
+    };
+    rest.trim()
+}
+
+//the first guid the firmware has a table for, so newer tables go first. the configuration table is
+//gone after exiting boot services
+pub fn find_config_table(st: &SystemTable<Boot>, guids: &[uefi::Guid]) -> Option<PhysAddr> {
+    guids.iter().find_map(|guid| {
+        st.config_table()
+            .iter()
+            .find(|entry| entry.guid == *guid)
+            .map(|entry| PhysAddr::new(entry.address as u64))
+    })
+}
+
+fn memory_region_kind(ty: MemoryType) -> MemoryRegionKind {
+    match ty {
+        MemoryType::CONVENTIONAL => MemoryRegionKind::Usable,
+        MemoryType::LOADER_CODE
+        | MemoryType::LOADER_DATA
+        | MemoryType::BOOT_SERVICES_CODE
+        | MemoryType::BOOT_SERVICES_DATA => MemoryRegionKind::Reclaimable,
+        KERNEL_MEMORY_TYPE => MemoryRegionKind::Kernel,
+        MODULE_MEMORY_TYPE => MemoryRegionKind::Module,
+        MemoryType::RUNTIME_SERVICES_CODE => MemoryRegionKind::RuntimeServicesCode,
+        MemoryType::RUNTIME_SERVICES_DATA => MemoryRegionKind::RuntimeServicesData,
+        MemoryType::ACPI_RECLAIM => MemoryRegionKind::AcpiReclaimable,
+        MemoryType::ACPI_NON_VOLATILE => MemoryRegionKind::AcpiNvs,
+        MemoryType::MMIO | MemoryType::MMIO_PORT_SPACE => MemoryRegionKind::Mmio,
+        MemoryType::PERSISTENT_MEMORY => MemoryRegionKind::Persistent,
+        MemoryType::UNUSABLE => MemoryRegionKind::Unusable,
+        _ => MemoryRegionKind::Reserved,
+    }
+}
+
+fn as_bytes<T>(val: &T) -> &[u8] {
+    unsafe { core::slice::from_raw_parts(val as *const T as *const u8, core::mem::size_of::<T>()) }
+}
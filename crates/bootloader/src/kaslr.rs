@@ -0,0 +1,69 @@
+use crate::arch::special::{KASLR_WINDOW_SIZE, KASLR_WINDOW_START};
+use crate::arch::{align_down, align_up, MapSize};
+use crate::eprintln;
+use crate::error::BootError;
+use uefi::prelude::*;
+use uefi::proto::rng::Rng;
+
+//keeps 2MiB pages possible for the kernel
+const KASLR_ALIGN: u64 = MapSize::Large.bytes();
+
+//what gets added to every virtual address in the kernel ELF
+pub fn choose_kernel_slide(
+    kernel_file: &xmas_elf::ElfFile,
+    st: &SystemTable<Boot>,
+) -> Result<u64, BootError> {
+    match kernel_file.header.pt2.type_().as_type() {
+        xmas_elf::header::Type::Executable => Ok(0),
+        xmas_elf::header::Type::SharedObject => {
+            let (lowest, highest) = kernel_file
+                .program_iter()
+                .filter(|ph| matches!(ph.get_type(), Ok(xmas_elf::program::Type::Load)))
+                .fold((u64::MAX, 0), |(lowest, highest), ph| {
+                    (
+                        core::cmp::min(lowest, ph.virtual_addr()),
+                        core::cmp::max(highest, ph.virtual_addr() + ph.mem_size()),
+                    )
+                });
+            if lowest >= highest {
+                return Err(BootError::Elf("kernel has no load segments"));
+            }
+
+            let link_base = align_down(lowest, KASLR_ALIGN);
+            let kernel_size = align_up(highest - link_base, KASLR_ALIGN);
+            if kernel_size > KASLR_WINDOW_SIZE {
+                return Err(BootError::KernelTooBig);
+            }
+
+            let num_slots = (KASLR_WINDOW_SIZE - kernel_size) / KASLR_ALIGN + 1;
+            let slot = random_u64(st) % num_slots;
+            let load_base = KASLR_WINDOW_START + slot * KASLR_ALIGN;
+            eprintln!(
+                "loading position independent kernel at {:#x} (slot {} of {})",
+                load_base, slot, num_slots
+            );
+
+            Ok(load_base - link_base)
+        }
+        other => Err(BootError::NotExecutable(other)),
+    }
+}
+
+fn random_u64(st: &SystemTable<Boot>) -> u64 {
+    if let Some(random) = uefi_random_u64(st) {
+        return random;
+    }
+    eprintln!("no UEFI RNG protocol, falling back to the cpu");
+
+    crate::arch::special::cpu_random_u64()
+}
+
+fn uefi_random_u64(st: &SystemTable<Boot>) -> Option<u64> {
+    let boot_services = st.boot_services();
+    let handle = boot_services.get_handle_for_protocol::<Rng>().ok()?;
+    let mut rng = boot_services.open_protocol_exclusive::<Rng>(handle).ok()?;
+
+    let mut buf = [0u8; 8];
+    rng.get_rng(None, &mut buf).ok()?;
+    Some(u64::from_le_bytes(buf))
+}
@@ -1,100 +1,321 @@
 #![no_main]
 #![no_std]
 
+extern crate alloc;
+
+mod arch;
+mod config;
 mod elf_mapper;
+mod error;
 mod frame_allocator;
+mod framebuffer;
+mod handoff;
+mod kaslr;
 mod make_stack;
+mod menu;
+mod modules;
 mod read_file;
+mod runtime;
+mod symbols;
+mod tls;
+mod verify;
 
+use alloc::format;
+use alloc::string::String;
+use core::sync::atomic::{AtomicBool, Ordering};
+use error::BootError;
 use uefi::prelude::*;
 
-use x86_64::structures::paging::{PageSize, PhysFrame, Mapper};
-use x86_64::VirtAddr;
-use x86_64::{structures::paging::Size4KiB, PhysAddr};
-
-static mut WRITER: AndyWriter = AndyWriter {};
+use arch::special::PHYSICAL_MEMORY_OFFSET;
+use arch::{KernelPageTable, MapFlags, MapSize, PhysAddr, VirtAddr};
 
-const NUM_STACK_PAGES: u64 = 100;
 const UEFI_PHYSICAL_OFFSET: u64 = 0; //UEFI uses identity mapping
 
+//everything read from disk and the firmware, so nothing after exiting boot services can fail on a file
+struct PreparedBoot {
+    kernel: elf_mapper::LoadedKernel,
+    kernel_slide: u64,
+    modules: modules::Modules,
+    symbols: Option<symbols::LoadedSymbols>,
+    cmdline: String,
+    stack_pages: u64,
+    framebuffer: Option<boot_info::FrameBufferInfo>,
+    kernel_measurement: boot_info::Measurement,
+    initrd_measurement: Option<boot_info::Measurement>,
+    rsdp_addr: Option<PhysAddr>,
+    smbios_addr: Option<PhysAddr>,
+    device_tree_addr: Option<PhysAddr>,
+}
+
 #[entry]
 fn main(image: Handle, mut st: SystemTable<Boot>) -> Status {
     uefi_services::init(&mut st).unwrap();
+    error::set_boot_services_active(true);
+
+    let prepared = match prepare_boot(image, &mut st) {
+        Ok(prepared) => prepared,
+        Err(err) => error::fail_and_reboot(&err, &mut st),
+    };
+
+    eprintln!("exiting boot services");
+    error::set_boot_services_active(false);
+    let (system_table, memory_map) =
+        st.exit_boot_services(uefi::table::boot::MemoryType::LOADER_DATA);
+
+    match start_kernel(prepared, system_table, memory_map) {
+        Ok(never) => match never {},
+        Err(err) => error::fail_and_halt(&err),
+    }
+}
+
+fn prepare_boot(image: Handle, st: &mut SystemTable<Boot>) -> Result<PreparedBoot, BootError> {
+    let boot_config = config::load_config(image, st);
+    let entry = &boot_config.entries[menu::choose_entry(&boot_config, st)];
+    config::set_verbosity(entry.verbosity);
+    if config::verbosity() >= config::Verbosity::Verbose {
+        eprintln!("{:#?}", boot_config);
+    }
+    eprintln!("booting entry {:?}", entry.name);
 
     eprintln!("Reading kernel file");
-    let kernel_slice = read_file::load_file_from_disk("efi\\kernel\\kernel", image, &st).unwrap();
-    let kernel_addr: PhysAddr = PhysAddr::new(kernel_slice as *const [u8] as *const u8 as u64);
-    assert!(kernel_addr.is_aligned(Size4KiB::SIZE));
+    let kernel_slice = read_file::load_file(
+        &entry.kernel,
+        entry.source,
+        uefi::table::boot::MemoryType::LOADER_DATA,
+        image,
+        st,
+    )?;
     eprintln!("Finished reading kernel file");
+    let kernel_measurement =
+        verify::verify_image(&entry.kernel, entry.source, kernel_slice, image, st)?;
+
+    let modules = modules::load_modules(&entry.initrd, &entry.modules, entry.source, image, st)?;
+    let initrd_measurement = match modules.initrd.as_ref() {
+        Some(initrd) => Some(verify::verify_image(
+            &entry.initrd,
+            entry.source,
+            initrd.data(),
+            image,
+            st,
+        )?),
+        None => None,
+    };
+    //extra modules are only ever read from disk, and have nowhere in the handoff to put a measurement
+    for module in modules.extra.iter() {
+        let path = format!("{}\\{}", entry.modules, module.name);
+        verify::verify_image(&path, read_file::FileSource::Disk, module.data(), image, st)?;
+    }
 
     eprintln!("Parsing ELF file");
-    let kernel_elf = xmas_elf::ElfFile::new(kernel_slice).unwrap();
+    let kernel_elf = xmas_elf::ElfFile::new(kernel_slice).map_err(BootError::Elf)?;
+    elf_mapper::check_kernel_header(&kernel_elf)?;
     eprintln!("Successfully parsed ELF file");
 
-    eprintln!("exiting boot services");
-    let (system_table, mut memory_map) =
-        st.exit_boot_services(uefi::table::boot::MemoryType::LOADER_DATA);
+    //the config's command line unless the entry asks for the one the bootloader was started with
+    let mut cmdline = entry.cmdline.clone();
+    if entry.load_options {
+        let load_options = handoff::read_cmdline(image, st);
+        if !load_options.is_empty() {
+            cmdline = load_options;
+        }
+    }
+    let kernel_slide = kaslr::choose_kernel_slide(&kernel_elf, st)?;
+    let framebuffer = framebuffer::init_framebuffer(image, st);
+    let rsdp_addr = handoff::find_config_table(
+        st,
+        &[uefi::table::cfg::ACPI2_GUID, uefi::table::cfg::ACPI_GUID],
+    );
+    let smbios_addr = handoff::find_config_table(
+        st,
+        &[
+            uefi::table::cfg::SMBIOS3_GUID,
+            uefi::table::cfg::SMBIOS_GUID,
+        ],
+    );
+    let device_tree_addr = handoff::find_config_table(st, &[handoff::DEVICE_TREE_GUID]);
+    eprintln!(
+        "rsdp at {:?}, smbios at {:?}, device tree at {:?}",
+        rsdp_addr, smbios_addr, device_tree_addr
+    );
+
+    eprintln!("Copying ELF segments into memory");
+    let kernel = elf_mapper::load_elf(&kernel_elf, kernel_slide, st)?;
+    let symbols = symbols::load_symbols(&kernel_elf, st)?;
+    read_file::free_file(kernel_slice, st)?;
+    eprintln!("Successfully copied ELF segments into memory");
+
+    Ok(PreparedBoot {
+        kernel,
+        kernel_slide,
+        modules,
+        symbols,
+        cmdline,
+        stack_pages: boot_config.stack_pages,
+        framebuffer,
+        kernel_measurement,
+        initrd_measurement,
+        rsdp_addr,
+        smbios_addr,
+        device_tree_addr,
+    })
+}
 
-    eprintln!("enabling write protection on ring 0");
-    enable_write_protect_bit();
-    eprintln!("enabling no execute flag");
-    enable_nxe_bit();
+//only returns if something went wrong, there is no console or allocator anymore at this point
+fn start_kernel(
+    prepared: PreparedBoot,
+    system_table: SystemTable<uefi::table::Runtime>,
+    mut memory_map: uefi::table::boot::MemoryMap<'static>,
+) -> Result<core::convert::Infallible, BootError> {
+    let PreparedBoot {
+        kernel,
+        kernel_slide,
+        modules,
+        symbols,
+        cmdline,
+        stack_pages,
+        framebuffer,
+        kernel_measurement,
+        initrd_measurement,
+        rsdp_addr,
+        smbios_addr,
+        device_tree_addr,
+    } = prepared;
+
+    arch::special::enable_protection();
 
     eprintln!("bruh");
     memory_map.sort();
+    if config::verbosity() >= config::Verbosity::Verbose {
+        for descriptor in memory_map.entries() {
+            eprintln!(
+                "{:#x} {:>8} pages {:?}",
+                descriptor.phys_start, descriptor.page_count, descriptor.ty
+            );
+        }
+    }
+    let mut max_physical_addr = elf_mapper::max_physical_address(&memory_map);
+    //the framebuffer isn't always in the memory map, the kernel still needs to reach it
+    if let Some(framebuffer) = framebuffer.as_ref() {
+        max_physical_addr =
+            max_physical_addr.max(PhysAddr::new(framebuffer.addr + framebuffer.size));
+    }
     let mut frame_allocator =
         frame_allocator::AndyFrameAllocator::new(memory_map.entries().copied());
 
     eprintln!("Mapping ELF file to virtual memory");
-    let (mut kernel_page_table, kernel_page_table_top_frame) =
-        elf_mapper::map_elf_into_memory(kernel_addr, &kernel_elf, &mut frame_allocator);
+    let mut kernel_page_table = elf_mapper::map_elf_into_memory(
+        &kernel,
+        &mut frame_allocator,
+        VirtAddr::new(PHYSICAL_MEMORY_OFFSET),
+        max_physical_addr,
+    )?;
     eprintln!("Successfully mapped ELF file to virtual memory");
 
-    let entry_point = VirtAddr::new(kernel_elf.header.pt2.entry_point());
-    let stack_top = make_stack::make_stack(&mut frame_allocator, NUM_STACK_PAGES);
+    modules::map_modules(&mut kernel_page_table, &mut frame_allocator, &modules)?;
+    if let Some(symbols) = symbols.as_ref() {
+        symbols::map_symbols(&mut kernel_page_table, &mut frame_allocator, symbols)?;
+    }
+    let runtime_services_addr = runtime::enter_virtual_mode(
+        system_table,
+        &memory_map,
+        &mut kernel_page_table,
+        &mut frame_allocator,
+    )?;
+
+    let stack_top = make_stack::make_stack(
+        &mut kernel_page_table,
+        &mut frame_allocator,
+        stack_pages,
+        kernel.stack_executable,
+    )?;
+    let thread_pointer = match kernel.tls.as_ref() {
+        Some(template) => Some(tls::make_boot_tls(
+            &mut kernel_page_table,
+            &mut frame_allocator,
+            template,
+        )?),
+        None => None,
+    };
+
+    let handoff_info = handoff::HandoffInfo {
+        physical_memory_offset: VirtAddr::new(PHYSICAL_MEMORY_OFFSET),
+        kernel_slide,
+        cmdline: &cmdline,
+        framebuffer,
+        modules: &modules,
+        symbols: symbols.as_ref(),
+        kernel_measurement,
+        initrd_measurement,
+        tls_template: kernel.tls,
+        rsdp_addr,
+        smbios_addr,
+        device_tree_addr,
+        runtime_services_addr,
+    };
+    let boot_info_region = handoff::BootInfoRegion::allocate(
+        &mut kernel_page_table,
+        &mut frame_allocator,
+        //each used range can split a usable region in up to three
+        memory_map.entries().len() + 2 * frame_allocator::MAX_USED_RANGES,
+        &handoff_info,
+    )?;
 
     eprintln!("identity mapping context switch function");
-    let context_switch_function = PhysAddr::new(context_switch as *const () as u64);
-    let context_switch_function_start_frame: PhysFrame =
-        PhysFrame::containing_address(context_switch_function);
-    for frame in PhysFrame::range_inclusive(
-        context_switch_function_start_frame,
-        context_switch_function_start_frame + 1,
-    ) {
-        unsafe {
-            let tlb = kernel_page_table
-                .identity_map(
-                    frame,
-                    x86_64::structures::paging::PageTableFlags::PRESENT,
-                    &mut frame_allocator,
-                )
-                .unwrap();
-            tlb.flush();
-        };
+    let context_switch_function = arch::special::context_switch as *const () as u64;
+    let context_switch_page = context_switch_function & !(MapSize::Small.bytes() - 1);
+    for page in [
+        context_switch_page,
+        context_switch_page + MapSize::Small.bytes(),
+    ] {
+        kernel_page_table.map(
+            VirtAddr::new(page),
+            PhysAddr::new(page),
+            MapSize::Small,
+            MapFlags::READ_EXECUTE,
+            &mut frame_allocator,
+        )?;
     }
     eprintln!("DONE identity mapping context switch function");
 
+    let boot_info_addr = boot_info_region.write(
+        &kernel_page_table,
+        &memory_map,
+        &frame_allocator,
+        &handoff_info,
+    );
+
     unsafe {
-        context_switch(kernel_page_table_top_frame, stack_top, entry_point);
+        arch::special::context_switch(
+            kernel_page_table.root(),
+            stack_top,
+            kernel.entry_point,
+            boot_info_addr,
+            thread_pointer,
+        );
     }
 }
 
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
-    use core::arch::asm;
-
-    eprintln!("error: {}", info);
+    //a panic while showing the last one just halts
+    static PANICKED: AtomicBool = AtomicBool::new(false);
+    if PANICKED.swap(true, Ordering::SeqCst) {
+        error::fail_and_halt(info);
+    }
 
-    loop {
-        unsafe { asm!("cli; hlt") };
+    if error::boot_services_active() {
+        let mut st = uefi_services::system_table();
+        error::fail_and_reboot(info, &mut st);
     }
+    error::fail_and_halt(info)
 }
 
 pub fn print(args: core::fmt::Arguments) {
     use core::fmt::Write;
-    unsafe {
-        WRITER.write_fmt(args).unwrap();
+    if config::verbosity() == config::Verbosity::Quiet {
+        return;
     }
+    AndyWriter {}.write_fmt(args).unwrap();
 }
 
 struct AndyWriter {}
@@ -102,10 +323,7 @@ struct AndyWriter {}
 impl core::fmt::Write for AndyWriter {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
         for b in s.bytes() {
-            unsafe {
-                let port: u16 = 0xe9; //qemu -debugcon
-                core::arch::asm!("outb %al, %dx", in("al") b, in("dx") port, options(att_syntax));
-            };
+            arch::special::debug_write_byte(b);
         }
         Ok(())
     }
@@ -121,31 +339,3 @@ macro_rules! eprintln {
     () => ($crate::print!("\n"));
     ($($arg:tt)*) => ($crate::eprint!("{}\n", format_args!($($arg)*)));
 }
-
-unsafe fn context_switch(page_table: PhysFrame, stack_top: VirtAddr, entry_point: VirtAddr) -> ! {
-    unsafe {
-        core::arch::asm!(
-            r#"
-            xor rbp, rbp
-            mov cr3, {}
-            mov rsp, {}
-            jmp {}
-            "#,
-            in(reg) page_table.start_address().as_u64(),
-            in(reg) stack_top.as_u64(),
-            in(reg) entry_point.as_u64(),
-        );
-    }
-
-    unreachable!()
-}
-
-fn enable_nxe_bit() {
-    use x86_64::registers::control::{Efer, EferFlags};
-    unsafe { Efer::update(|efer| *efer |= EferFlags::NO_EXECUTE_ENABLE) }
-}
-
-fn enable_write_protect_bit() {
-    use x86_64::registers::control::{Cr0, Cr0Flags};
-    unsafe { Cr0::update(|cr0| *cr0 |= Cr0Flags::WRITE_PROTECT) };
-}
@@ -1,11 +1,55 @@
-use x86_64::VirtAddr;
+use crate::arch::special::{PageTable, STACK_GUARD_PAGE_ADDR};
+use crate::arch::{
+    FrameAllocator, KernelPageTable, MapFlags, MapSize, Page, PhysFrame, VirtAddr, PAGE_SIZE,
+};
+use crate::elf_mapper::uefi_get_addr;
+use crate::eprintln;
+use crate::error::BootError;
 use crate::frame_allocator::AndyFrameAllocator;
 
+pub fn make_stack(
+    kernel_page_table: &mut PageTable,
+    frame_allocator: &mut AndyFrameAllocator,
+    num_pages: u64,
+    executable: bool,
+) -> Result<VirtAddr, BootError> {
+    assert!(num_pages > 0);
 
+    let guard_page: Page = Page::from_start_address(VirtAddr::new(STACK_GUARD_PAGE_ADDR)).unwrap();
+    let stack_start_page = guard_page + 1;
+    let stack_end_page = stack_start_page + num_pages;
 
-pub fn make_stack(frame_allocator: &mut AndyFrameAllocator, num_pages: u64) -> VirtAddr{
-    //todo
-    return VirtAddr::new(1234);
-}
+    eprintln!(
+        "making stack of {} pages at {:?} with guard page {:?}",
+        num_pages, stack_start_page, guard_page
+    );
+
+    let stack_flags = MapFlags {
+        execute: executable,
+        ..MapFlags::READ_WRITE
+    };
+
+    for page in Page::range(stack_start_page, stack_end_page) {
+        let frame: PhysFrame = frame_allocator
+            .allocate_frame()
+            .ok_or(BootError::OutOfFrames("the stack"))?;
 
+        let frame_ptr = uefi_get_addr(frame.start_address()).as_mut_ptr::<u8>();
+        unsafe { core::ptr::write_bytes(frame_ptr, 0, PAGE_SIZE as usize) };
 
+        kernel_page_table.map(
+            page.start_address(),
+            frame.start_address(),
+            MapSize::Small,
+            stack_flags,
+            frame_allocator,
+        )?;
+    }
+
+    assert!(kernel_page_table
+        .translate(guard_page.start_address())
+        .is_none());
+
+    //stack grows down so the top is the end of the last page, already 16 byte aligned
+    Ok(stack_end_page.start_address())
+}
@@ -0,0 +1,129 @@
+use crate::config::BootConfig;
+use crate::eprintln;
+use core::fmt::Write;
+use uefi::prelude::*;
+use uefi::proto::console::text::{Key, ScanCode};
+use uefi::table::boot::{EventType, TimerTrigger, Tpl};
+use uefi::Event;
+
+//timer is in units of 100ns
+const ONE_SECOND: u64 = 10_000_000;
+
+//counts down on the firmware console, any key stops the countdown, enter boots the highlighted entry.
+//a console that doesn't work just boots the default entry
+pub fn choose_entry(config: &BootConfig, st: &mut SystemTable<Boot>) -> usize {
+    if config.entries.len() < 2 || config.timeout_secs == 0 {
+        return config.default_entry;
+    }
+
+    match run_menu(config, st) {
+        Ok(selected) => selected,
+        Err(err) => {
+            eprintln!(
+                "boot menu failed: {:?}, booting the default entry",
+                err.status()
+            );
+            config.default_entry
+        }
+    }
+}
+
+fn run_menu(config: &BootConfig, st: &mut SystemTable<Boot>) -> uefi::Result<usize> {
+    let timer = unsafe {
+        st.boot_services()
+            .create_event(EventType::TIMER, Tpl::APPLICATION, None, None)?
+    };
+    let selected = menu_loop(config, &timer, st);
+    //closing also cancels it. an entry that got picked still boots if cleaning up fails
+    if let Err(err) = st.boot_services().close_event(timer) {
+        eprintln!("can't close the menu timer: {:?}", err.status());
+    }
+    if let Err(err) = st.stdout().clear() {
+        eprintln!("can't clear the console: {:?}", err.status());
+    }
+    selected
+}
+
+fn menu_loop(
+    config: &BootConfig,
+    timer: &Event,
+    st: &mut SystemTable<Boot>,
+) -> uefi::Result<usize> {
+    let mut selected = config.default_entry;
+    let key_event = st
+        .stdin()
+        .wait_for_key_event()
+        .ok_or(uefi::Error::from(Status::UNSUPPORTED))?;
+
+    let mut remaining = Some(config.timeout_secs);
+    loop {
+        draw_menu(config, selected, remaining, st)?;
+
+        if remaining == Some(0) {
+            break;
+        }
+        if remaining.is_some() {
+            st.boot_services()
+                .set_timer(timer, TimerTrigger::Relative(ONE_SECOND))?;
+        }
+
+        let mut events = unsafe { [key_event.unsafe_clone(), timer.unsafe_clone()] };
+        let num_events = if remaining.is_some() { 2 } else { 1 };
+        let index = st
+            .boot_services()
+            .wait_for_event(&mut events[..num_events])
+            .map_err(|err| err.to_err_without_payload())?;
+
+        if index == 1 {
+            remaining = remaining.map(|secs| secs - 1);
+            continue;
+        }
+
+        remaining = None;
+        match st.stdin().read_key()? {
+            Some(Key::Special(ScanCode::UP)) => selected = selected.saturating_sub(1),
+            Some(Key::Special(ScanCode::DOWN)) => {
+                selected = (selected + 1).min(config.entries.len() - 1)
+            }
+            Some(Key::Printable(c)) if c == '\r' || c == '\n' => break,
+            Some(Key::Printable(c)) => {
+                //digits pick an entry straight away
+                if let Some(digit) = char::from(c).to_digit(10) {
+                    let index = digit as usize;
+                    if index > 0 && index <= config.entries.len() {
+                        selected = index - 1;
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(selected)
+}
+
+fn draw_menu(
+    config: &BootConfig,
+    selected: usize,
+    remaining: Option<u64>,
+    st: &mut SystemTable<Boot>,
+) -> uefi::Result {
+    let stdout = st.stdout();
+    stdout.clear()?;
+    writeln!(stdout, "select a boot entry:\r").map_err(console_error)?;
+    for (i, entry) in config.entries.iter().enumerate() {
+        let marker = if i == selected { '>' } else { ' ' };
+        writeln!(stdout, "{} {}. {}\r", marker, i + 1, entry.name).map_err(console_error)?;
+    }
+    match remaining {
+        Some(secs) => writeln!(stdout, "\r\nbooting in {}s\r", secs),
+        None => writeln!(stdout, "\r\nup/down to move, enter to boot\r"),
+    }
+    .map_err(console_error)
+}
+
+//fmt::Write only says that something failed
+fn console_error(_: core::fmt::Error) -> uefi::Error {
+    Status::DEVICE_ERROR.into()
+}
@@ -0,0 +1,144 @@
+use crate::arch::special::{PageTable, MODULES_ADDR};
+use crate::arch::{KernelPageTable, MapFlags, Page, PhysAddr, VirtAddr, PAGE_SIZE};
+use crate::eprintln;
+use crate::error::BootError;
+use crate::frame_allocator::AndyFrameAllocator;
+use crate::read_file::{self, FileSource};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use uefi::prelude::*;
+use uefi::table::boot::MemoryType;
+
+//kept apart from the kernel's pages so the kernel knows which memory it can free once it's done with them
+pub const MODULE_MEMORY_TYPE: MemoryType = MemoryType::custom(0x8000_0001);
+
+pub struct LoadedModule {
+    pub name: String,
+    pub phys_start: PhysAddr,
+    pub virt_start: VirtAddr,
+    pub len: u64,
+}
+
+impl LoadedModule {
+    //only while the bootloader still runs on UEFI's identity mapping
+    pub fn data(&self) -> &[u8] {
+        unsafe {
+            core::slice::from_raw_parts(self.phys_start.as_u64() as *const u8, self.len as usize)
+        }
+    }
+
+    fn page_range(&self) -> (Page, Page) {
+        let start_page = Page::containing_address(self.virt_start);
+        //empty modules still got a page from the firmware
+        let end_page = start_page + (self.len.max(1) - 1) / 4096;
+        (start_page, end_page)
+    }
+}
+
+pub struct Modules {
+    pub initrd: Option<LoadedModule>,
+    pub extra: Vec<LoadedModule>,
+}
+
+impl Modules {
+    pub fn iter(&self) -> impl Iterator<Item = &LoadedModule> {
+        self.initrd.iter().chain(self.extra.iter())
+    }
+}
+
+//has to happen before exiting boot services, missing files are fine, anything else is not
+pub fn load_modules(
+    initrd_path: &str,
+    modules_dir: &str,
+    source: FileSource,
+    image: Handle,
+    st: &SystemTable<Boot>,
+) -> Result<Modules, BootError> {
+    let mut next_addr = VirtAddr::new(MODULES_ADDR);
+
+    let initrd = load_module(
+        "initrd".to_string(),
+        initrd_path,
+        source,
+        &mut next_addr,
+        image,
+        st,
+    )?;
+
+    //tftp can't list directories, so extra modules only ever come from disk
+    let names = match source {
+        FileSource::Tftp => Vec::new(),
+        FileSource::Disk | FileSource::DiskThenTftp => {
+            match read_file::list_directory(modules_dir, image, st) {
+                Ok(names) => names,
+                Err(err) if err.is_not_found() => Vec::new(),
+                Err(err) => {
+                    eprintln!("not loading extra modules, {}", err);
+                    Vec::new()
+                }
+            }
+        }
+    };
+    let mut extra = Vec::new();
+    for name in names {
+        let path = format!("{}\\{}", modules_dir, name);
+        if let Some(module) = load_module(name, &path, FileSource::Disk, &mut next_addr, image, st)?
+        {
+            extra.push(module);
+        }
+    }
+
+    Ok(Modules { initrd, extra })
+}
+
+fn load_module(
+    name: String,
+    path: &str,
+    source: FileSource,
+    next_addr: &mut VirtAddr,
+    image: Handle,
+    st: &SystemTable<Boot>,
+) -> Result<Option<LoadedModule>, BootError> {
+    let file = match read_file::load_file(path, source, MODULE_MEMORY_TYPE, image, st) {
+        Ok(file) => file,
+        Err(err) if err.is_not_found() => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    let module = LoadedModule {
+        name,
+        phys_start: PhysAddr::new(file.as_ptr() as u64),
+        virt_start: *next_addr,
+        len: file.len() as u64,
+    };
+    let (_, end_page) = module.page_range();
+    *next_addr = (end_page + 2).start_address();
+
+    eprintln!(
+        "loaded module {} ({} bytes) at {:?}",
+        module.name, module.len, module.phys_start
+    );
+
+    Ok(Some(module))
+}
+
+//read only, the kernel copies out whatever it wants to change
+pub fn map_modules(
+    kernel_page_table: &mut PageTable,
+    frame_allocator: &mut AndyFrameAllocator,
+    modules: &Modules,
+) -> Result<(), BootError> {
+    for module in modules.iter() {
+        let (start_page, end_page) = module.page_range();
+        kernel_page_table.map_pages(
+            start_page.start_address(),
+            module.phys_start.align_down(PAGE_SIZE),
+            end_page - start_page + 1,
+            MapFlags::READ,
+            frame_allocator,
+        )?;
+    }
+
+    Ok(())
+}
@@ -1,56 +1,252 @@
+use crate::error::BootError;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
 use core::ops::Deref;
 use core::ops::DerefMut;
 use uefi::prelude::*;
 use uefi::proto::media::file::File;
+use uefi::proto::network::pxe::{BaseCode, DhcpV4Packet};
+use uefi::proto::network::IpAddress;
+use uefi::table::boot::MemoryType;
+
+//tftp error code for a file the server doesn't have
+const TFTP_FILE_NOT_FOUND: u8 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileSource {
+    Disk,
+    Tftp,
+    //tftp only when the file isn't on disk, e.g. when the bootloader itself came over the network
+    DiskThenTftp,
+}
+
+pub fn load_file(
+    name: &str,
+    source: FileSource,
+    memory_type: MemoryType,
+    image: Handle,
+    st: &SystemTable<Boot>,
+) -> Result<&'static mut [u8], BootError> {
+    match source {
+        FileSource::Disk => load_file_from_disk(name, memory_type, image, st),
+        FileSource::Tftp => load_file_from_tftp(name, memory_type, image, st),
+        FileSource::DiskThenTftp => match load_file_from_disk(name, memory_type, image, st) {
+            //only a missing file goes to the network, a disk that fails to read is its own problem.
+            //what the server says is the more useful error then, a file it doesn't have is still
+            //not found
+            Err(err) if err.is_not_found() => load_file_from_tftp(name, memory_type, image, st),
+            result => result,
+        },
+    }
+}
 
 pub fn load_file_from_disk(
     name: &str,
+    memory_type: MemoryType,
     image: Handle,
     st: &SystemTable<Boot>,
-) -> Result<&'static mut [u8], uefi::Error> {
+) -> Result<&'static mut [u8], BootError> {
+    let file_error = |err| BootError::file(name, err);
     let mut file_system_raw =
-        locate_and_open_protocol::<uefi::proto::media::fs::SimpleFileSystem>(image, st)?;
+        locate_and_open_protocol::<uefi::proto::media::fs::SimpleFileSystem>(image, st)
+            .map_err(file_error)?;
     let file_system = file_system_raw.deref_mut();
 
-    let mut root = file_system.open_volume()?;
+    let mut root = file_system.open_volume().map_err(file_error)?;
     let mut buf = [0u16; 256];
 
-    let filename =
-        uefi::CStr16::from_str_with_buf(name, &mut buf).expect("Failed to convert string to utf16");
+    let filename = uefi::CStr16::from_str_with_buf(name, &mut buf)
+        .map_err(|_| BootError::file(name, Status::INVALID_PARAMETER.into()))?;
 
-    let file_handle = root.open(
-        filename,
-        uefi::proto::media::file::FileMode::Read,
-        uefi::proto::media::file::FileAttribute::empty(),
-    )?;
+    let file_handle = root
+        .open(
+            filename,
+            uefi::proto::media::file::FileMode::Read,
+            uefi::proto::media::file::FileAttribute::empty(),
+        )
+        .map_err(file_error)?;
 
-    let mut file = match file_handle.into_type()? {
+    let mut file = match file_handle.into_type().map_err(file_error)? {
         uefi::proto::media::file::FileType::Regular(f) => f,
-        uefi::proto::media::file::FileType::Dir(_) => panic!(),
+        uefi::proto::media::file::FileType::Dir(_) => {
+            return Err(BootError::NotAFile(name.to_string()))
+        }
     };
 
     let file_info = file
         .get_boxed_info::<uefi::proto::media::file::FileInfo>()
-        .unwrap();
-    let file_size = usize::try_from(file_info.file_size()).unwrap();
+        .map_err(|err| BootError::file(name, err.to_err_without_payload()))?;
+    let file_size = usize::try_from(file_info.file_size())
+        .map_err(|_| BootError::file(name, Status::BAD_BUFFER_SIZE.into()))?;
+
+    let file_ptr = st
+        .boot_services()
+        .allocate_pages(
+            uefi::table::boot::AllocateType::AnyPages,
+            memory_type,
+            file_pages(file_size),
+        )
+        .map_err(file_error)? as *mut u8;
+
+    unsafe { core::ptr::write_bytes(file_ptr, 0, file_size) };
+    let file_slice = unsafe { core::slice::from_raw_parts_mut(file_ptr, file_size) };
+    if let Err(err) = file.read(file_slice) {
+        unsafe {
+            st.boot_services()
+                .free_pages(file_ptr as u64, file_pages(file_size))
+        }
+        .map_err(file_error)?;
+        return Err(BootError::file(name, err.to_err_without_payload()));
+    }
+
+    Ok(file_slice)
+}
+
+//names of the regular files directly inside a directory, sorted so the order doesn't depend on the firmware
+pub fn list_directory(
+    name: &str,
+    image: Handle,
+    st: &SystemTable<Boot>,
+) -> Result<Vec<String>, BootError> {
+    let dir_error = |err| BootError::file(name, err);
+    let mut file_system_raw =
+        locate_and_open_protocol::<uefi::proto::media::fs::SimpleFileSystem>(image, st)
+            .map_err(dir_error)?;
+    let file_system = file_system_raw.deref_mut();
+
+    let mut root = file_system.open_volume().map_err(dir_error)?;
+    let mut buf = [0u16; 256];
+
+    let dirname = uefi::CStr16::from_str_with_buf(name, &mut buf)
+        .map_err(|_| BootError::file(name, Status::INVALID_PARAMETER.into()))?;
+
+    let dir_handle = root
+        .open(
+            dirname,
+            uefi::proto::media::file::FileMode::Read,
+            uefi::proto::media::file::FileAttribute::empty(),
+        )
+        .map_err(dir_error)?;
+
+    let mut dir = match dir_handle.into_type().map_err(dir_error)? {
+        uefi::proto::media::file::FileType::Dir(d) => d,
+        uefi::proto::media::file::FileType::Regular(_) => {
+            return Err(BootError::file(name, uefi::Status::NOT_FOUND.into()))
+        }
+    };
+
+    let mut names = Vec::new();
+    while let Some(entry) = dir
+        .read_entry_boxed()
+        .map_err(|err| BootError::file(name, err.to_err_without_payload()))?
+    {
+        if !entry.is_directory() {
+            names.push(entry.file_name().to_string());
+        }
+    }
+    names.sort();
+
+    Ok(names)
+}
+
+pub fn load_file_from_tftp(
+    name: &str,
+    memory_type: MemoryType,
+    image: Handle,
+    st: &SystemTable<Boot>,
+) -> Result<&'static mut [u8], BootError> {
+    load_file_from_tftp_raw(name, memory_type, image, st).map_err(|err| BootError::file(name, err))
+}
+
+//from the server that gave us our dhcp lease, paths use / instead of backslashes there
+fn load_file_from_tftp_raw(
+    name: &str,
+    memory_type: MemoryType,
+    image: Handle,
+    st: &SystemTable<Boot>,
+) -> Result<&'static mut [u8], uefi::Error> {
+    let this = st.boot_services();
+    let handle = this.get_handle_for_protocol::<BaseCode>()?;
+    let mut base_code = unsafe {
+        this.open_protocol::<BaseCode>(
+            uefi::table::boot::OpenProtocolParams {
+                handle,
+                agent: image,
+                controller: None,
+            },
+            uefi::table::boot::OpenProtocolAttributes::GetProtocol,
+        )
+    }?;
+
+    //already done by the firmware if it loaded us over pxe
+    if !base_code.mode().started {
+        base_code.start(false)?;
+    }
+    if !base_code.mode().dhcp_ack_received {
+        base_code.dhcp(false)?;
+    }
+
+    let dhcp_ack: &DhcpV4Packet = base_code.mode().dhcp_ack.as_ref();
+    let server_ip = IpAddress::new_v4(dhcp_ack.bootp_si_addr);
 
-    let file_ptr = st.boot_services().allocate_pages(
+    let mut path: Vec<u8> = name
+        .bytes()
+        .map(|b| if b == b'\\' { b'/' } else { b })
+        .collect();
+    path.push(0);
+    //a nul in the middle of the name
+    let filename = uefi::CStr8::from_bytes_with_nul(&path)
+        .map_err(|_| uefi::Error::from(Status::INVALID_PARAMETER))?;
+
+    let file_size = match base_code.tftp_get_file_size(&server_ip, filename) {
+        Ok(file_size) => {
+            usize::try_from(file_size).map_err(|_| uefi::Error::from(Status::BAD_BUFFER_SIZE))?
+        }
+        Err(err) => {
+            let mode = base_code.mode();
+            if mode.tftp_error_received && mode.tftp_error.error_code == TFTP_FILE_NOT_FOUND {
+                return Err(Status::NOT_FOUND.into());
+            }
+            return Err(err);
+        }
+    };
+
+    let file_ptr = this.allocate_pages(
         uefi::table::boot::AllocateType::AnyPages,
-        uefi::table::boot::MemoryType::LOADER_DATA,
-        ((file_size - 1) / 4096) + 1,
+        memory_type,
+        file_pages(file_size),
     )? as *mut u8;
 
     unsafe { core::ptr::write_bytes(file_ptr, 0, file_size) };
     let file_slice = unsafe { core::slice::from_raw_parts_mut(file_ptr, file_size) };
-    file.read(file_slice).unwrap();
+    if file_size > 0 {
+        if let Err(err) = base_code.tftp_read_file(&server_ip, filename, Some(&mut *file_slice)) {
+            unsafe { this.free_pages(file_ptr as u64, file_pages(file_size)) }?;
+            return Err(err);
+        }
+    }
 
     Ok(file_slice)
 }
 
+pub fn free_file(file: &'static mut [u8], st: &SystemTable<Boot>) -> Result<(), BootError> {
+    let file_addr = file.as_mut_ptr() as u64;
+    unsafe {
+        st.boot_services()
+            .free_pages(file_addr, file_pages(file.len()))
+    }
+    .map_err(BootError::firmware("freeing a file"))
+}
+
+//empty files still get a page so there is always something to free
+fn file_pages(file_size: usize) -> usize {
+    ((file_size.max(1) - 1) / 4096) + 1
+}
+
 fn locate_and_open_protocol<P: uefi::proto::ProtocolPointer>(
     image: Handle,
     st: &SystemTable<Boot>,
-) -> Result<uefi::table::boot::ScopedProtocol<P>, uefi::Error> {
+) -> Result<uefi::table::boot::ScopedProtocol<'_, P>, uefi::Error> {
     let this = st.boot_services();
     let device_path = open_device_path_protocol(image, st)?;
     let mut device_path = device_path.deref();
@@ -74,7 +270,8 @@ fn locate_and_open_protocol<P: uefi::proto::ProtocolPointer>(
 fn open_device_path_protocol(
     image: Handle,
     st: &SystemTable<Boot>,
-) -> Result<uefi::table::boot::ScopedProtocol<uefi::proto::device_path::DevicePath>, uefi::Error> {
+) -> Result<uefi::table::boot::ScopedProtocol<'_, uefi::proto::device_path::DevicePath>, uefi::Error>
+{
     let this = st.boot_services();
     let device_handle = unsafe {
         this.open_protocol::<uefi::proto::loaded_image::LoadedImage>(
@@ -88,7 +285,7 @@ fn open_device_path_protocol(
     }?
     .deref()
     .device()
-    .unwrap();
+    .ok_or(uefi::Error::from(Status::NOT_FOUND))?;
 
     let device_path = unsafe {
         this.open_protocol::<uefi::proto::device_path::DevicePath>(
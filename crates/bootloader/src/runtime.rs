@@ -0,0 +1,174 @@
+use crate::arch::special::{PageTable, RUNTIME_SERVICES_OFFSET};
+use crate::arch::{KernelPageTable, MapFlags, MapSize, PhysAddr, VirtAddr};
+use crate::eprintln;
+use crate::error::BootError;
+use crate::frame_allocator::AndyFrameAllocator;
+use uefi::table::boot::{MemoryAttribute, MemoryDescriptor, MemoryMap, MemoryType};
+use uefi::table::{Runtime, SystemTable};
+use uefi::{guid, Guid};
+
+//the allocator is gone after exiting boot services, so the virtual map lives on the stack
+const MAX_RUNTIME_REGIONS: usize = 64;
+
+//splits the runtime images into read only code and no execute data, which the memory map can't
+const MEMORY_ATTRIBUTES_TABLE_GUID: Guid = guid!("dcfa911d-26eb-469f-a220-38b7dc461f4c");
+
+#[repr(C)]
+struct MemoryAttributesTableHeader {
+    version: u32,
+    num_entries: u32,
+    //the entries are memory descriptors this far apart
+    descriptor_size: u32,
+    flags: u32,
+}
+
+struct MemoryAttributesTable {
+    entries: *const u8,
+    num_entries: usize,
+    descriptor_size: usize,
+}
+
+impl MemoryAttributesTable {
+    //the firmware's identity map still has to be up
+    fn find(system_table: &SystemTable<Runtime>) -> Option<Self> {
+        let entry = system_table
+            .config_table()
+            .iter()
+            .find(|entry| entry.guid == MEMORY_ATTRIBUTES_TABLE_GUID)?;
+        let header = unsafe { &*(entry.address as *const MemoryAttributesTableHeader) };
+        Some(MemoryAttributesTable {
+            entries: unsafe { (header as *const MemoryAttributesTableHeader).add(1) } as *const u8,
+            num_entries: header.num_entries as usize,
+            descriptor_size: header.descriptor_size as usize,
+        })
+    }
+
+    fn entries(&self) -> impl Iterator<Item = MemoryDescriptor> + '_ {
+        (0..self.num_entries).map(|i| unsafe {
+            (self.entries.add(i * self.descriptor_size) as *const MemoryDescriptor).read_unaligned()
+        })
+    }
+
+    //the type and attributes for the page at addr, if the table says anything about it
+    fn lookup(&self, addr: u64) -> Option<(MemoryType, MemoryAttribute)> {
+        self.entries()
+            .find(|entry| {
+                let end = entry.phys_start + entry.page_count * MapSize::Small.bytes();
+                entry.phys_start <= addr && addr < end
+            })
+            .map(|entry| (entry.ty, entry.att))
+    }
+}
+
+//maps the runtime regions into the kernel and tells the firmware about it, returns where the kernel
+//finds the runtime services table or None if the firmware doesn't play along. an error means the
+//kernel's page table is broken, not just the runtime services
+pub fn enter_virtual_mode(
+    system_table: SystemTable<Runtime>,
+    memory_map: &MemoryMap,
+    kernel_page_table: &mut PageTable,
+    frame_allocator: &mut AndyFrameAllocator,
+) -> Result<Option<VirtAddr>, BootError> {
+    let mut virtual_map = [MemoryDescriptor::default(); MAX_RUNTIME_REGIONS];
+    let mut num_regions = 0;
+    for descriptor in memory_map.entries() {
+        if !descriptor.att.contains(MemoryAttribute::RUNTIME) {
+            continue;
+        }
+        if num_regions == MAX_RUNTIME_REGIONS {
+            eprintln!("too many runtime regions, not keeping runtime services");
+            return Ok(None);
+        }
+        let mut descriptor = *descriptor;
+        descriptor.virt_start = RUNTIME_SERVICES_OFFSET + descriptor.phys_start;
+        virtual_map[num_regions] = descriptor;
+        num_regions += 1;
+    }
+    let virtual_map = &mut virtual_map[..num_regions];
+
+    let system_table_phys = system_table.get_current_system_table_addr();
+    //the table hasn't moved yet, so this is still its physical address
+    let runtime_services_phys = unsafe { system_table.runtime_services() } as *const _ as u64;
+    let in_runtime_region = |addr: u64| {
+        virtual_map.iter().any(|descriptor| {
+            let end = descriptor.phys_start + descriptor.page_count * MapSize::Small.bytes();
+            descriptor.phys_start <= addr && addr < end
+        })
+    };
+    if !in_runtime_region(system_table_phys) || !in_runtime_region(runtime_services_phys) {
+        eprintln!("system table isn't in runtime memory, not keeping runtime services");
+        return Ok(None);
+    }
+
+    let attributes_table = MemoryAttributesTable::find(&system_table);
+    for descriptor in virtual_map.iter() {
+        map_runtime_region(
+            kernel_page_table,
+            frame_allocator,
+            descriptor,
+            attributes_table.as_ref(),
+        )?;
+    }
+
+    //still running on the firmware's identity map, so the firmware can fix itself up through it
+    let result = unsafe {
+        system_table
+            .set_virtual_address_map(virtual_map, RUNTIME_SERVICES_OFFSET + system_table_phys)
+    };
+    if let Err(err) = result {
+        eprintln!("SetVirtualAddressMap failed: {:?}", err);
+        return Ok(None);
+    }
+
+    eprintln!("moved {} runtime regions to virtual addresses", num_regions);
+    Ok(Some(VirtAddr::new(
+        RUNTIME_SERVICES_OFFSET + runtime_services_phys,
+    )))
+}
+
+//the firmware relocates itself through its identity map, so code never has to be writable here.
+//code is read only and data no execute, the attributes table can only take more away
+fn map_runtime_region(
+    kernel_page_table: &mut PageTable,
+    frame_allocator: &mut AndyFrameAllocator,
+    descriptor: &MemoryDescriptor,
+    attributes_table: Option<&MemoryAttributesTable>,
+) -> Result<(), BootError> {
+    //ro and xp in the memory map are only what the memory could do, the table is what it should do
+    let flags_at = |addr: u64| {
+        let (ty, att) = attributes_table
+            .and_then(|table| table.lookup(addr))
+            .unwrap_or((descriptor.ty, MemoryAttribute::empty()));
+        let code = ty == MemoryType::RUNTIME_SERVICES_CODE;
+        MapFlags {
+            write: !code && !att.contains(MemoryAttribute::READ_ONLY),
+            execute: code && !att.contains(MemoryAttribute::EXECUTE_PROTECT),
+            uncached: !descriptor.att.contains(MemoryAttribute::WRITE_BACK),
+        }
+    };
+
+    //runs of pages with the same flags get mapped together
+    let page_size = MapSize::Small.bytes();
+    let mut run_start = 0;
+    let mut run_flags = flags_at(descriptor.phys_start);
+    for i in 1..=descriptor.page_count {
+        let flags =
+            (i < descriptor.page_count).then(|| flags_at(descriptor.phys_start + i * page_size));
+        if flags == Some(run_flags) {
+            continue;
+        }
+        kernel_page_table.map_pages(
+            VirtAddr::new(descriptor.virt_start + run_start * page_size),
+            PhysAddr::new(descriptor.phys_start + run_start * page_size),
+            i - run_start,
+            run_flags,
+            frame_allocator,
+        )?;
+        if let Some(flags) = flags {
+            run_start = i;
+            run_flags = flags;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,122 @@
+use crate::arch::special::{PageTable, SYMBOLS_ADDR};
+use crate::arch::{KernelPageTable, MapFlags, PhysAddr, VirtAddr, PAGE_SIZE};
+use crate::elf_mapper::KERNEL_MEMORY_TYPE;
+use crate::eprintln;
+use crate::error::BootError;
+use crate::frame_allocator::AndyFrameAllocator;
+use uefi::prelude::*;
+
+const SYMBOL_SIZE: u64 = 24; //Elf64_Sym
+
+//.symtab followed by its .strtab, copied out of the kernel file before it's freed
+pub struct LoadedSymbols {
+    phys_start: PhysAddr,
+    num_symbols: u64,
+    names_len: u64,
+}
+
+impl LoadedSymbols {
+    pub fn symbols_addr(&self) -> VirtAddr {
+        VirtAddr::new(SYMBOLS_ADDR)
+    }
+
+    pub fn num_symbols(&self) -> u64 {
+        self.num_symbols
+    }
+
+    //right after the symbols, which keeps them 8 byte aligned
+    pub fn names_addr(&self) -> VirtAddr {
+        self.symbols_addr() + self.num_symbols * SYMBOL_SIZE
+    }
+
+    pub fn names_len(&self) -> u64 {
+        self.names_len
+    }
+
+    fn num_pages(&self) -> u64 {
+        (self.num_symbols * SYMBOL_SIZE + self.names_len).div_ceil(PAGE_SIZE)
+    }
+}
+
+//a stripped kernel just boots without symbols
+pub fn load_symbols(
+    kernel_file: &xmas_elf::ElfFile,
+    st: &SystemTable<Boot>,
+) -> Result<Option<LoadedSymbols>, BootError> {
+    use xmas_elf::sections::ShType;
+
+    let Some(symtab) = kernel_file
+        .section_iter()
+        .find(|section| section.get_type() == Ok(ShType::SymTab))
+    else {
+        eprintln!("kernel has no symbol table");
+        return Ok(None);
+    };
+    let strtab = kernel_file
+        .section_header(symtab.link() as u16)
+        .map_err(BootError::Elf)?;
+    if strtab.get_type() != Ok(ShType::StrTab) {
+        return Err(BootError::Elf(
+            "symbol table doesn't link to a string table",
+        ));
+    }
+    if symtab.entry_size() as u64 != SYMBOL_SIZE {
+        return Err(BootError::Elf("symbol table entries aren't Elf64_Sym"));
+    }
+
+    let section_data = |section: &xmas_elf::sections::SectionHeader| {
+        let start = section.offset() as usize;
+        let end = start.checked_add(section.size() as usize);
+        end.and_then(|end| kernel_file.input.get(start..end))
+            .ok_or(BootError::Elf("section is past the end of the file"))
+    };
+    let symbol_data = section_data(&symtab)?;
+    let name_data = section_data(&strtab)?;
+
+    let symbols = LoadedSymbols {
+        phys_start: PhysAddr::new(0),
+        num_symbols: symbol_data.len() as u64 / SYMBOL_SIZE,
+        names_len: name_data.len() as u64,
+    };
+    let start = st
+        .boot_services()
+        .allocate_pages(
+            uefi::table::boot::AllocateType::AnyPages,
+            KERNEL_MEMORY_TYPE,
+            symbols.num_pages().max(1) as usize,
+        )
+        .map_err(|_| BootError::OutOfFrames("the symbol table"))?;
+
+    //still identity mapped by UEFI so can write through the physical address
+    let symbols_len = (symbols.num_symbols * SYMBOL_SIZE) as usize;
+    unsafe {
+        let dest = start as *mut u8;
+        core::ptr::copy_nonoverlapping(symbol_data.as_ptr(), dest, symbols_len);
+        core::ptr::copy_nonoverlapping(name_data.as_ptr(), dest.add(symbols_len), name_data.len());
+    }
+
+    eprintln!(
+        "loaded {} symbols and {} bytes of names at {:#x}",
+        symbols.num_symbols, symbols.names_len, start
+    );
+
+    Ok(Some(LoadedSymbols {
+        phys_start: PhysAddr::new(start),
+        ..symbols
+    }))
+}
+
+//read only, like the modules
+pub fn map_symbols(
+    kernel_page_table: &mut PageTable,
+    frame_allocator: &mut AndyFrameAllocator,
+    symbols: &LoadedSymbols,
+) -> Result<(), BootError> {
+    kernel_page_table.map_pages(
+        symbols.symbols_addr(),
+        symbols.phys_start,
+        symbols.num_pages(),
+        MapFlags::READ,
+        frame_allocator,
+    )
+}
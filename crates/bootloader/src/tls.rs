@@ -0,0 +1,84 @@
+use crate::arch::special::{PageTable, BOOT_TLS_ADDR, TLS_VARIANT};
+use crate::arch::{
+    align_up, FrameAllocator, KernelPageTable, MapFlags, MapSize, Page, PhysFrame, TlsVariant,
+    VirtAddr, PAGE_SIZE,
+};
+use crate::elf_mapper::{read_from_kernel, uefi_get_addr, write_to_kernel};
+use crate::eprintln;
+use crate::error::BootError;
+use crate::frame_allocator::AndyFrameAllocator;
+use boot_info::TlsTemplate;
+
+//variant I (riscv64): the tls data starts right at the thread pointer.
+//variant II (x86_64): the tls data sits right below the thread pointer, and the thread pointer points at
+//the tcb, whose first word points at itself so the compiler can read fs:0 to get the thread pointer
+pub fn make_boot_tls(
+    kernel_page_table: &mut PageTable,
+    frame_allocator: &mut AndyFrameAllocator,
+    template: &TlsTemplate,
+) -> Result<VirtAddr, BootError> {
+    //the block starts on a page, so the thread pointer is aligned as long as align fits in a page
+    if template.align > PAGE_SIZE {
+        return Err(BootError::Elf("TLS alignment is bigger than a page"));
+    }
+    let block_start = VirtAddr::new(BOOT_TLS_ADDR);
+    let (thread_pointer, block_end) = match TLS_VARIANT {
+        TlsVariant::One => (block_start, block_start + template.mem_size.max(1)),
+        TlsVariant::Two => {
+            let tls_offset = align_up(template.mem_size, template.align);
+            let thread_pointer = block_start + tls_offset;
+            (
+                thread_pointer,
+                thread_pointer + core::mem::size_of::<u64>() as u64,
+            )
+        }
+    };
+
+    eprintln!(
+        "making {} byte TLS block with thread pointer {:?}",
+        block_end - block_start,
+        thread_pointer
+    );
+
+    let start_page: Page = Page::containing_address(block_start);
+    let end_page: Page = Page::containing_address(block_end - 1u64);
+    for page in Page::range_inclusive(start_page, end_page) {
+        let frame: PhysFrame = frame_allocator
+            .allocate_frame()
+            .ok_or(BootError::OutOfFrames("the TLS block"))?;
+        let frame_ptr = uefi_get_addr(frame.start_address()).as_mut_ptr::<u8>();
+        unsafe { core::ptr::write_bytes(frame_ptr, 0, PAGE_SIZE as usize) };
+
+        kernel_page_table.map(
+            page.start_address(),
+            frame.start_address(),
+            MapSize::Small,
+            MapFlags::READ_WRITE,
+            frame_allocator,
+        )?;
+    }
+
+    //the frames are already zeroed, which covers the tbss part
+    let mut buf = [0u8; 256];
+    let mut copied = 0;
+    while copied < template.file_size {
+        let chunk = core::cmp::min(buf.len() as u64, template.file_size - copied) as usize;
+        read_from_kernel(
+            kernel_page_table,
+            VirtAddr::new(template.start_addr + copied),
+            &mut buf[..chunk],
+        );
+        write_to_kernel(kernel_page_table, block_start + copied, &buf[..chunk]);
+        copied += chunk as u64;
+    }
+
+    if TLS_VARIANT == TlsVariant::Two {
+        write_to_kernel(
+            kernel_page_table,
+            thread_pointer,
+            &thread_pointer.as_u64().to_ne_bytes(),
+        );
+    }
+
+    Ok(thread_pointer)
+}
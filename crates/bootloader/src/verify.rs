@@ -0,0 +1,100 @@
+use crate::config::{self, Verbosity};
+use crate::eprintln;
+use crate::error::BootError;
+use crate::read_file::{self, FileSource};
+use alloc::format;
+use alloc::string::ToString;
+use boot_info::{Measurement, VerifyStatus};
+use core::fmt::Write;
+use ed25519_compact::{PublicKey, Signature};
+use uefi::prelude::*;
+use uefi::table::boot::MemoryType;
+
+//hex of the raw 32 byte ed25519 key, set when building the bootloader. signatures are the raw 64 bytes
+//in a .sig file next to the image, e.g.
+//  openssl pkeyutl -sign -rawin -inkey key.pem -in kernel -out kernel.sig
+//  openssl pkey -in key.pem -pubout -outform DER | tail -c 32 | xxd -p -c 32
+const PUBLIC_KEY: Option<[u8; PublicKey::BYTES]> = match option_env!("BOOTLOADER_PUBLIC_KEY") {
+    Some(hex) => Some(decode_key(hex)),
+    None => None,
+};
+
+//checks a whole image against its signature, a bad or missing signature refuses to boot
+pub fn verify_image(
+    path: &str,
+    source: FileSource,
+    image_data: &[u8],
+    image: Handle,
+    st: &mut SystemTable<Boot>,
+) -> Result<Measurement, BootError> {
+    let sha512 = ed25519_compact::sha512::Hash::hash(image_data);
+
+    let Some(key) = PUBLIC_KEY else {
+        warn_unchecked(path, st);
+        return Ok(Measurement {
+            status: VerifyStatus::Unchecked,
+            sha512,
+        });
+    };
+
+    let sig_path = format!("{}.sig", path);
+    let sig_file = read_file::load_file(&sig_path, source, MemoryType::LOADER_DATA, image, st)?;
+    let signature = Signature::from_slice(sig_file);
+    read_file::free_file(sig_file, st)?;
+
+    let result =
+        signature.and_then(|signature| PublicKey::from_slice(&key)?.verify(image_data, &signature));
+    if let Err(err) = result {
+        return Err(BootError::Signature {
+            path: path.to_string(),
+            err,
+        });
+    }
+
+    eprintln!("signature of {} is good", path);
+    Ok(Measurement {
+        status: VerifyStatus::Verified,
+        sha512,
+    })
+}
+
+//on screen and on the debug port even when quiet, nobody should boot something unchecked without knowing
+fn warn_unchecked(path: &str, st: &mut SystemTable<Boot>) {
+    let verbosity = config::verbosity();
+    config::set_verbosity(Verbosity::Normal);
+    eprintln!(
+        "warning: no public key built in, not checking the signature of {}",
+        path
+    );
+    config::set_verbosity(verbosity);
+
+    let _ = write!(
+        st.stdout(),
+        "warning: no public key built in, not checking the signature of {}\r\n",
+        path
+    );
+}
+
+const fn decode_key(hex: &str) -> [u8; PublicKey::BYTES] {
+    let hex = hex.as_bytes();
+    assert!(
+        hex.len() == 2 * PublicKey::BYTES,
+        "BOOTLOADER_PUBLIC_KEY has to be 64 hex digits"
+    );
+    let mut key = [0; PublicKey::BYTES];
+    let mut i = 0;
+    while i < key.len() {
+        key[i] = (hex_digit(hex[2 * i]) << 4) | hex_digit(hex[2 * i + 1]);
+        i += 1;
+    }
+    key
+}
+
+const fn hex_digit(c: u8) -> u8 {
+    match c {
+        b'0'..=b'9' => c - b'0',
+        b'a'..=b'f' => c - b'a' + 10,
+        b'A'..=b'F' => c - b'A' + 10,
+        _ => panic!("BOOTLOADER_PUBLIC_KEY has to be 64 hex digits"),
+    }
+}
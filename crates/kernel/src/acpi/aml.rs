@@ -0,0 +1,79 @@
+//just enough aml to read a sleep state package without an interpreter: a Name whose value is a
+//Package of integer constants, SLP_TYPa then SLP_TYPb
+const ZERO_OP: u8 = 0x00;
+const ONE_OP: u8 = 0x01;
+const NAME_OP: u8 = 0x08;
+const BYTE_PREFIX: u8 = 0x0a;
+const WORD_PREFIX: u8 = 0x0b;
+const DWORD_PREFIX: u8 = 0x0c;
+const QWORD_PREFIX: u8 = 0x0e;
+const PACKAGE_OP: u8 = 0x12;
+const ONES_OP: u8 = 0xff;
+const ROOT_CHAR: u8 = b'\\';
+
+//name like b"_S5_", the slp_typ values for pm1a and pm1b
+pub fn find_sleep_package(aml: &[u8], name: &[u8; 4]) -> Option<(u8, u8)> {
+    let mut start = 0;
+    while let Some(pos) = aml[start..]
+        .windows(name.len())
+        .position(|window| window == name)
+        .map(|pos| pos + start)
+    {
+        start = pos + 1;
+        //the name has to be what a NameOp defines, maybe from the root. anywhere else it's a use
+        let defined = (pos >= 1 && aml[pos - 1] == NAME_OP)
+            || (pos >= 2 && aml[pos - 1] == ROOT_CHAR && aml[pos - 2] == NAME_OP);
+        if !defined {
+            continue;
+        }
+        if let Some(types) = parse_sleep_package(&aml[pos + name.len()..]) {
+            return Some(types);
+        }
+    }
+    None
+}
+
+fn parse_sleep_package(aml: &[u8]) -> Option<(u8, u8)> {
+    let (&op, rest) = aml.split_first()?;
+    if op != PACKAGE_OP {
+        return None;
+    }
+    //the pkglength's top two bits say how many more bytes it has
+    let lead = *rest.first()?;
+    let rest = rest.get(1 + (lead >> 6) as usize..)?;
+    let (&num_elements, rest) = rest.split_first()?;
+    if num_elements == 0 {
+        return None;
+    }
+
+    let (slp_typa, rest) = parse_integer(rest)?;
+    let slp_typb = if num_elements >= 2 {
+        parse_integer(rest)?.0
+    } else {
+        0
+    };
+    Some((slp_typa as u8, slp_typb as u8))
+}
+
+fn parse_integer(aml: &[u8]) -> Option<(u64, &[u8])> {
+    let (&op, rest) = aml.split_first()?;
+    match op {
+        ZERO_OP => Some((0, rest)),
+        ONE_OP => Some((1, rest)),
+        ONES_OP => Some((u64::MAX, rest)),
+        BYTE_PREFIX => read_le(rest, 1),
+        WORD_PREFIX => read_le(rest, 2),
+        DWORD_PREFIX => read_le(rest, 4),
+        QWORD_PREFIX => read_le(rest, 8),
+        _ => None,
+    }
+}
+
+fn read_le(aml: &[u8], len: usize) -> Option<(u64, &[u8])> {
+    let bytes = aml.get(..len)?;
+    let val = bytes
+        .iter()
+        .rev()
+        .fold(0u64, |val, &byte| (val << 8) | byte as u64);
+    Some((val, &aml[len..]))
+}
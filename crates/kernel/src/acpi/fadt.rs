@@ -0,0 +1,176 @@
+use super::{aml, find_table, read, table, AcpiError, GenericAddress, SdtHeader};
+use core::mem::size_of;
+
+//the "FACP" table, the fixed hardware registers and where the dsdt is
+pub struct Fadt {
+    header: &'static SdtHeader,
+}
+
+//byte offsets from the start of the table, later revisions only add to the end
+const DSDT: usize = 40;
+const SMI_COMMAND: usize = 48;
+const ACPI_ENABLE: usize = 52;
+const PM1A_CONTROL_BLOCK: usize = 64;
+const PM1B_CONTROL_BLOCK: usize = 68;
+const PM1_CONTROL_LENGTH: usize = 89;
+const FLAGS: usize = 112;
+const RESET_REGISTER: usize = 116;
+const RESET_VALUE: usize = 128;
+const X_DSDT: usize = 140;
+const X_PM1A_CONTROL_BLOCK: usize = 172;
+const X_PM1B_CONTROL_BLOCK: usize = 184;
+
+const FLAG_RESET_REG_SUPPORTED: u32 = 1 << 10;
+
+//pm1 control register
+const SCI_ENABLE: u64 = 1 << 0;
+const SLP_TYP_SHIFT: u64 = 10;
+const SLP_TYP_MASK: u64 = 0b111 << SLP_TYP_SHIFT;
+const SLP_ENABLE: u64 = 1 << 13;
+
+//how long to wait for firmware to hand over, and for the machine to go away
+const SPIN_TRIES: usize = 10_000_000;
+
+impl Fadt {
+    pub fn get() -> Option<Fadt> {
+        find_table(b"FACP").map(|header| Fadt { header })
+    }
+
+    fn base(&self) -> *const u8 {
+        self.header as *const SdtHeader as *const u8
+    }
+
+    //fields past the end of an older, shorter fadt read as 0
+    fn field<T: Copy + Default>(&self, offset: usize) -> T {
+        if offset + size_of::<T>() > self.header.length as usize {
+            return T::default();
+        }
+        unsafe { read(self.base(), offset) }
+    }
+
+    fn generic_address(&self, offset: usize) -> Option<GenericAddress> {
+        if offset + 12 > self.header.length as usize {
+            return None;
+        }
+        Some(unsafe { GenericAddress::parse(self.base(), offset) }).filter(|gas| gas.is_present())
+    }
+
+    pub fn flags(&self) -> u32 {
+        self.field(FLAGS)
+    }
+
+    pub fn dsdt(&self) -> Option<&'static SdtHeader> {
+        let x_dsdt: u64 = self.field(X_DSDT);
+        let addr = if x_dsdt != 0 {
+            x_dsdt
+        } else {
+            self.field::<u32>(DSDT) as u64
+        };
+        if addr == 0 {
+            return None;
+        }
+        table(addr).ok()
+    }
+
+    //the extended address if there is one, the old port if not
+    pub fn pm1a_control(&self) -> Option<GenericAddress> {
+        self.generic_address(X_PM1A_CONTROL_BLOCK)
+            .or_else(|| self.legacy_pm1_control(PM1A_CONTROL_BLOCK))
+    }
+
+    pub fn pm1b_control(&self) -> Option<GenericAddress> {
+        self.generic_address(X_PM1B_CONTROL_BLOCK)
+            .or_else(|| self.legacy_pm1_control(PM1B_CONTROL_BLOCK))
+    }
+
+    fn legacy_pm1_control(&self, offset: usize) -> Option<GenericAddress> {
+        let port: u32 = self.field(offset);
+        let len: u8 = self.field(PM1_CONTROL_LENGTH);
+        (port != 0 && len != 0).then(|| GenericAddress::io_port(port, len))
+    }
+
+    //the register and what to write to it
+    pub fn reset_register(&self) -> Option<(GenericAddress, u8)> {
+        if self.flags() & FLAG_RESET_REG_SUPPORTED == 0 {
+            return None;
+        }
+        let register = self.generic_address(RESET_REGISTER)?;
+        Some((register, self.field(RESET_VALUE)))
+    }
+
+    //firmware that starts out in legacy mode ignores sleep until it's told acpi is in charge
+    unsafe fn enable_acpi_mode(&self, pm1a_control: &GenericAddress) -> Result<(), AcpiError> {
+        let smi_command: u32 = self.field(SMI_COMMAND);
+        let acpi_enable: u8 = self.field(ACPI_ENABLE);
+        //hardware reduced, or already in acpi mode
+        if smi_command == 0 || acpi_enable == 0 || unsafe { pm1a_control.read()? } & SCI_ENABLE != 0
+        {
+            return Ok(());
+        }
+
+        unsafe { GenericAddress::io_port(smi_command, 1).write(acpi_enable as u64)? };
+        for _ in 0..SPIN_TRIES {
+            if unsafe { pm1a_control.read()? } & SCI_ENABLE != 0 {
+                break;
+            }
+            core::hint::spin_loop();
+        }
+        Ok(())
+    }
+}
+
+//s5, soft off. only returns if the machine is still on, with why
+pub fn poweroff() -> AcpiError {
+    match enter_s5() {
+        Ok(()) => AcpiError::StillRunning,
+        Err(err) => err,
+    }
+}
+
+fn enter_s5() -> Result<(), AcpiError> {
+    let fadt = Fadt::get().ok_or(AcpiError::NoTable(*b"FACP"))?;
+    let dsdt = fadt.dsdt().ok_or(AcpiError::NoTable(*b"DSDT"))?;
+    let aml = unsafe {
+        core::slice::from_raw_parts(
+            (dsdt as *const SdtHeader as *const u8).add(size_of::<SdtHeader>()),
+            dsdt.length as usize - size_of::<SdtHeader>(),
+        )
+    };
+    let (slp_typa, slp_typb) = aml::find_sleep_package(aml, b"_S5_").ok_or(AcpiError::NoS5)?;
+    let pm1a_control = fadt.pm1a_control().ok_or(AcpiError::NoPm1Control)?;
+
+    unsafe {
+        fadt.enable_acpi_mode(&pm1a_control)?;
+
+        let val = pm1a_control.read()? & !SLP_TYP_MASK;
+        pm1a_control.write(val | (slp_typa as u64) << SLP_TYP_SHIFT | SLP_ENABLE)?;
+        if let Some(pm1b_control) = fadt.pm1b_control() {
+            let val = pm1b_control.read()? & !SLP_TYP_MASK;
+            pm1b_control.write(val | (slp_typb as u64) << SLP_TYP_SHIFT | SLP_ENABLE)?;
+        }
+    }
+
+    for _ in 0..SPIN_TRIES {
+        core::hint::spin_loop();
+    }
+    Ok(())
+}
+
+//through the fadt's reset register. only returns if the machine is still on, with why
+pub fn reset() -> AcpiError {
+    match write_reset_register() {
+        Ok(()) => AcpiError::StillRunning,
+        Err(err) => err,
+    }
+}
+
+fn write_reset_register() -> Result<(), AcpiError> {
+    let fadt = Fadt::get().ok_or(AcpiError::NoTable(*b"FACP"))?;
+    let (register, value) = fadt.reset_register().ok_or(AcpiError::NoResetRegister)?;
+    unsafe { register.write(value as u64)? };
+
+    for _ in 0..SPIN_TRIES {
+        core::hint::spin_loop();
+    }
+    Ok(())
+}
@@ -0,0 +1,30 @@
+use super::{find_table, read, GenericAddress, SdtHeader};
+use core::mem::size_of;
+
+//the "HPET" table, where the event timer block is and what it has
+#[derive(Clone, Copy, Debug)]
+pub struct Hpet {
+    pub num_comparators: u8,
+    pub counter_64_bit: bool,
+    pub base_address: GenericAddress,
+    pub number: u8,
+}
+
+impl Hpet {
+    pub fn get() -> Option<Hpet> {
+        let header = find_table(b"HPET")?;
+        let base = header as *const SdtHeader as *const u8;
+        let fields_start = size_of::<SdtHeader>();
+        if (header.length as usize) < fields_start + 20 {
+            return None;
+        }
+
+        let block_id: u32 = unsafe { read(base, fields_start) };
+        Some(Hpet {
+            num_comparators: ((block_id >> 8) & 0x1f) as u8 + 1,
+            counter_64_bit: block_id & (1 << 13) != 0,
+            base_address: unsafe { GenericAddress::parse(base, fields_start + 4) },
+            number: unsafe { read(base, fields_start + 16) },
+        })
+    }
+}
@@ -0,0 +1,96 @@
+use super::{find_table, read, SdtHeader};
+use core::mem::size_of;
+
+//the "APIC" table, the interrupt controllers and cpus
+pub struct Madt {
+    header: &'static SdtHeader,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum MadtEntry {
+    LocalApic {
+        flags: u32,
+    },
+    IoApic {
+        addr: u32,
+        gsi_base: u32,
+    },
+    //isa irqs that aren't wired to the gsi with the same number, or aren't edge triggered active high
+    InterruptSourceOverride {
+        bus: u8,
+        source: u8,
+        gsi: u32,
+        flags: u16,
+    },
+    LocalX2Apic {
+        flags: u32,
+    },
+    //nmi wiring and the rest, nothing here looks at them yet
+    Other,
+}
+
+//flags in a local apic or local x2apic entry, whether that cpu can be started
+pub const CPU_ENABLED: u32 = 1 << 0;
+pub const CPU_ONLINE_CAPABLE: u32 = 1 << 1;
+
+//flags in an interrupt source override
+pub const MPS_POLARITY_MASK: u16 = 0b11;
+pub const MPS_POLARITY_ACTIVE_LOW: u16 = 0b11;
+pub const MPS_TRIGGER_MASK: u16 = 0b11 << 2;
+pub const MPS_TRIGGER_LEVEL: u16 = 0b11 << 2;
+
+const MADT_ENTRIES_OFFSET: usize = size_of::<SdtHeader>() + 8;
+
+impl Madt {
+    pub fn get() -> Option<Madt> {
+        find_table(b"APIC").map(|header| Madt { header })
+    }
+
+    fn base(&self) -> *const u8 {
+        self.header as *const SdtHeader as *const u8
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = MadtEntry> + '_ {
+        let base = self.base();
+        let end = self.header.length as usize;
+        let mut offset = MADT_ENTRIES_OFFSET;
+        core::iter::from_fn(move || {
+            if offset + 2 > end {
+                return None;
+            }
+            let ty: u8 = unsafe { read(base, offset) };
+            let len: u8 = unsafe { read(base, offset + 1) };
+            //a zero length entry would never end
+            if len < 2 || offset + len as usize > end {
+                return None;
+            }
+            let entry = unsafe { parse_madt_entry(base, offset, ty) };
+            offset += len as usize;
+            Some(entry)
+        })
+    }
+}
+
+unsafe fn parse_madt_entry(base: *const u8, offset: usize, ty: u8) -> MadtEntry {
+    unsafe {
+        match ty {
+            0 => MadtEntry::LocalApic {
+                flags: read(base, offset + 4),
+            },
+            1 => MadtEntry::IoApic {
+                addr: read(base, offset + 4),
+                gsi_base: read(base, offset + 8),
+            },
+            2 => MadtEntry::InterruptSourceOverride {
+                bus: read(base, offset + 2),
+                source: read(base, offset + 3),
+                gsi: read(base, offset + 4),
+                flags: read(base, offset + 8),
+            },
+            9 => MadtEntry::LocalX2Apic {
+                flags: read(base, offset + 8),
+            },
+            _ => MadtEntry::Other,
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use super::{find_table, read, SdtHeader};
+use core::mem::size_of;
+
+//the "MCFG" table, where pcie config space is memory mapped
+pub struct Mcfg {
+    header: &'static SdtHeader,
+}
+
+//one range of buses in one segment
+#[derive(Clone, Copy, Debug)]
+pub struct McfgEntry {
+    pub base_address: u64,
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+const MCFG_ENTRIES_OFFSET: usize = size_of::<SdtHeader>() + 8;
+const MCFG_ENTRY_SIZE: usize = 16;
+
+impl Mcfg {
+    pub fn get() -> Option<Mcfg> {
+        find_table(b"MCFG").map(|header| Mcfg { header })
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = McfgEntry> + '_ {
+        let base = self.header as *const SdtHeader as *const u8;
+        let len = self.header.length as usize;
+        let num_entries = len.saturating_sub(MCFG_ENTRIES_OFFSET) / MCFG_ENTRY_SIZE;
+        (0..num_entries).map(move |i| {
+            let offset = MCFG_ENTRIES_OFFSET + i * MCFG_ENTRY_SIZE;
+            unsafe {
+                McfgEntry {
+                    base_address: read(base, offset),
+                    segment: read(base, offset + 8),
+                    start_bus: read(base, offset + 10),
+                    end_bus: read(base, offset + 11),
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,329 @@
+//finding acpi tables, reached through the physical memory map
+pub mod aml;
+pub mod fadt;
+pub mod hpet;
+pub mod madt;
+pub mod mcfg;
+
+use core::mem::size_of;
+
+#[derive(Debug)]
+pub enum AcpiError {
+    BadRsdp,
+    BadChecksum([u8; 4]),
+    NotInitialized,
+    NoTable([u8; 4]),
+    //the dsdt doesn't have the \_S5 package, or it isn't plain enough to read without an interpreter
+    NoS5,
+    NoPm1Control,
+    NoResetRegister,
+    UnsupportedAddressSpace(u8),
+    UnsupportedAccessSize(u8),
+    //the registers were written but the machine is still going
+    StillRunning,
+}
+
+impl core::fmt::Display for AcpiError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            AcpiError::BadRsdp => write!(f, "bad rsdp"),
+            AcpiError::BadChecksum(sig) => write!(f, "bad checksum on {}", signature(sig)),
+            AcpiError::NotInitialized => write!(f, "acpi isn't set up"),
+            AcpiError::NoTable(sig) => write!(f, "no {} table", signature(sig)),
+            AcpiError::NoS5 => write!(f, "no \\_S5 package that can be read"),
+            AcpiError::NoPm1Control => write!(f, "no pm1 control register"),
+            AcpiError::NoResetRegister => write!(f, "no reset register"),
+            AcpiError::UnsupportedAddressSpace(space) => {
+                write!(f, "registers in address space {} aren't supported", space)
+            }
+            AcpiError::UnsupportedAccessSize(size) => {
+                write!(f, "{} bit register accesses aren't supported", size)
+            }
+            AcpiError::StillRunning => write!(f, "the machine is still running"),
+        }
+    }
+}
+
+fn signature(sig: &[u8; 4]) -> &str {
+    core::str::from_utf8(sig).unwrap_or("????")
+}
+
+//the header every system description table starts with
+#[repr(C, packed)]
+#[derive(Clone, Copy, Debug)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_addr: u32,
+    //only from revision 2 on
+    length: u32,
+    xsdt_addr: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+struct Acpi {
+    physical_memory_offset: u64,
+    //the xsdt if there is one, it has 64 bit pointers where the rsdt has 32 bit ones
+    root: u64,
+    root_entry_size: usize,
+}
+
+static ACPI: spin::Once<Acpi> = spin::Once::new();
+
+pub fn init(rsdp_addr: u64, physical_memory_offset: u64) -> Result<(), AcpiError> {
+    let rsdp_ptr = (physical_memory_offset + rsdp_addr) as *const u8;
+    let rsdp: Rsdp = unsafe { read(rsdp_ptr, 0) };
+    if &rsdp.signature != b"RSD PTR " || !checksum_ok(rsdp_ptr, 20) {
+        return Err(AcpiError::BadRsdp);
+    }
+
+    let (root, root_entry_size) = if rsdp.revision >= 2 && rsdp.xsdt_addr != 0 {
+        if !checksum_ok(rsdp_ptr, rsdp.length as usize) {
+            return Err(AcpiError::BadRsdp);
+        }
+        (rsdp.xsdt_addr, size_of::<u64>())
+    } else {
+        (rsdp.rsdt_addr as u64, size_of::<u32>())
+    };
+
+    let acpi = Acpi {
+        physical_memory_offset,
+        root,
+        root_entry_size,
+    };
+    table_at(&acpi, root)?;
+    ACPI.call_once(|| acpi);
+    Ok(())
+}
+
+//where the kernel sees a physical address from a table
+pub fn phys_to_virt(phys: u64) -> Option<u64> {
+    ACPI.get().map(|acpi| acpi.physical_memory_offset + phys)
+}
+
+//a table that isn't in the rsdt, like the dsdt
+pub fn table(phys: u64) -> Result<&'static SdtHeader, AcpiError> {
+    let acpi = ACPI.get().ok_or(AcpiError::NotInitialized)?;
+    table_at(acpi, phys)
+}
+
+//the first table with the signature that also has the right checksum
+pub fn find_table(signature: &[u8; 4]) -> Option<&'static SdtHeader> {
+    let acpi = ACPI.get()?;
+    let root = table_at(acpi, acpi.root).ok()?;
+    let entries_start = root as *const SdtHeader as *const u8;
+    let num_entries = (root.length as usize - size_of::<SdtHeader>()) / acpi.root_entry_size;
+
+    (0..num_entries)
+        .map(|i| {
+            let offset = size_of::<SdtHeader>() + i * acpi.root_entry_size;
+            if acpi.root_entry_size == size_of::<u64>() {
+                unsafe { read::<u64>(entries_start, offset) }
+            } else {
+                unsafe { read::<u32>(entries_start, offset) as u64 }
+            }
+        })
+        .filter_map(|addr| table_at(acpi, addr).ok())
+        .find(|table| &table.signature == signature)
+}
+
+fn table_at(acpi: &Acpi, phys: u64) -> Result<&'static SdtHeader, AcpiError> {
+    let ptr = (acpi.physical_memory_offset + phys) as *const u8;
+    let header = unsafe { &*(ptr as *const SdtHeader) };
+    if !checksum_ok(ptr, header.length as usize) {
+        return Err(AcpiError::BadChecksum(header.signature));
+    }
+    Ok(header)
+}
+
+//all the bytes add up to 0
+fn checksum_ok(ptr: *const u8, len: usize) -> bool {
+    let bytes = unsafe { core::slice::from_raw_parts(ptr, len) };
+    bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+}
+
+/// # Safety
+/// base + offset has to be mapped for size_of::<T>() bytes
+pub unsafe fn read<T: Copy>(base: *const u8, offset: usize) -> T {
+    unsafe { (base.add(offset) as *const T).read_unaligned() }
+}
+
+//a register somewhere, as the fadt and hpet tables describe them
+#[derive(Clone, Copy, Debug)]
+pub struct GenericAddress {
+    pub address_space: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    //1 to 4 for byte to qword, 0 if only bit_width says
+    pub access_size: u8,
+    pub address: u64,
+}
+
+pub const ADDRESS_SPACE_MEMORY: u8 = 0;
+pub const ADDRESS_SPACE_IO: u8 = 1;
+pub const ADDRESS_SPACE_PCI_CONFIG: u8 = 2;
+
+impl GenericAddress {
+    /// # Safety
+    /// base + offset has to be mapped for the 12 bytes of the address
+    pub unsafe fn parse(base: *const u8, offset: usize) -> Self {
+        unsafe {
+            GenericAddress {
+                address_space: read(base, offset),
+                bit_width: read(base, offset + 1),
+                bit_offset: read(base, offset + 2),
+                access_size: read(base, offset + 3),
+                address: read(base, offset + 4),
+            }
+        }
+    }
+
+    //fixed hardware registers only in the old 32 bit fields, which are always port i/o
+    pub fn io_port(port: u32, len: u8) -> Self {
+        GenericAddress {
+            address_space: ADDRESS_SPACE_IO,
+            bit_width: len * 8,
+            bit_offset: 0,
+            access_size: 0,
+            address: port as u64,
+        }
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.address != 0
+    }
+
+    fn access_bytes(&self) -> Result<u8, AcpiError> {
+        match self.access_size {
+            0 => match self.bit_width {
+                8 | 16 | 32 | 64 => Ok(self.bit_width / 8),
+                _ => Err(AcpiError::UnsupportedAccessSize(self.bit_width)),
+            },
+            1..=4 => Ok(1 << (self.access_size - 1)),
+            other => Err(AcpiError::UnsupportedAccessSize(other)),
+        }
+    }
+
+    /// # Safety
+    /// reading the register can't break anything
+    pub unsafe fn read(&self) -> Result<u64, AcpiError> {
+        Ok(unsafe { self.read_access()? } >> self.bit_offset)
+    }
+
+    /// # Safety
+    /// writing the register can do anything, it's how acpi turns the machine off
+    pub unsafe fn write(&self, val: u64) -> Result<(), AcpiError> {
+        unsafe { self.write_access(val << self.bit_offset) }
+    }
+
+    //the whole access, without bit_offset
+    unsafe fn read_access(&self) -> Result<u64, AcpiError> {
+        let bytes = self.access_bytes()?;
+        match self.address_space {
+            ADDRESS_SPACE_MEMORY => {
+                let addr = phys_to_virt(self.address).ok_or(AcpiError::NotInitialized)?;
+                Ok(unsafe {
+                    match bytes {
+                        1 => (addr as *const u8).read_volatile() as u64,
+                        2 => (addr as *const u16).read_volatile() as u64,
+                        4 => (addr as *const u32).read_volatile() as u64,
+                        _ => (addr as *const u64).read_volatile(),
+                    }
+                })
+            }
+            #[cfg(target_arch = "x86_64")]
+            ADDRESS_SPACE_IO => unsafe { port_read(self.address as u16, bytes) },
+            #[cfg(target_arch = "x86_64")]
+            ADDRESS_SPACE_PCI_CONFIG => unsafe {
+                port_write(PCI_CONFIG_ADDRESS, pci_config_address(self.address), 4)?;
+                port_read(PCI_CONFIG_DATA + (self.address as u16 & 0b11), bytes)
+            },
+            other => Err(AcpiError::UnsupportedAddressSpace(other)),
+        }
+    }
+
+    unsafe fn write_access(&self, val: u64) -> Result<(), AcpiError> {
+        let bytes = self.access_bytes()?;
+        match self.address_space {
+            ADDRESS_SPACE_MEMORY => {
+                let addr = phys_to_virt(self.address).ok_or(AcpiError::NotInitialized)?;
+                unsafe {
+                    match bytes {
+                        1 => (addr as *mut u8).write_volatile(val as u8),
+                        2 => (addr as *mut u16).write_volatile(val as u16),
+                        4 => (addr as *mut u32).write_volatile(val as u32),
+                        _ => (addr as *mut u64).write_volatile(val),
+                    }
+                }
+                Ok(())
+            }
+            #[cfg(target_arch = "x86_64")]
+            ADDRESS_SPACE_IO => unsafe { port_write(self.address as u16, val, bytes) },
+            #[cfg(target_arch = "x86_64")]
+            ADDRESS_SPACE_PCI_CONFIG => unsafe {
+                port_write(PCI_CONFIG_ADDRESS, pci_config_address(self.address), 4)?;
+                port_write(PCI_CONFIG_DATA + (self.address as u16 & 0b11), val, bytes)
+            },
+            other => Err(AcpiError::UnsupportedAddressSpace(other)),
+        }
+    }
+}
+
+//config mechanism 1, segment 0 and bus 0 are all a pci config generic address can name
+#[cfg(target_arch = "x86_64")]
+const PCI_CONFIG_ADDRESS: u16 = 0xcf8;
+#[cfg(target_arch = "x86_64")]
+const PCI_CONFIG_DATA: u16 = 0xcfc;
+
+//device in bits 32 to 47, function in 16 to 31 and the register in 0 to 15
+#[cfg(target_arch = "x86_64")]
+fn pci_config_address(address: u64) -> u64 {
+    let device = (address >> 32) & 0x1f;
+    let function = (address >> 16) & 0x7;
+    let register = address & 0xfc;
+    1 << 31 | device << 11 | function << 8 | register
+}
+
+#[cfg(target_arch = "x86_64")]
+unsafe fn port_read(port: u16, bytes: u8) -> Result<u64, AcpiError> {
+    use x86_64::instructions::port::Port;
+    unsafe {
+        match bytes {
+            1 => Ok(Port::<u8>::new(port).read() as u64),
+            2 => Ok(Port::<u16>::new(port).read() as u64),
+            4 => Ok(Port::<u32>::new(port).read() as u64),
+            other => Err(AcpiError::UnsupportedAccessSize(other * 8)),
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+unsafe fn port_write(port: u16, val: u64, bytes: u8) -> Result<(), AcpiError> {
+    use x86_64::instructions::port::Port;
+    unsafe {
+        match bytes {
+            1 => Port::<u8>::new(port).write(val as u8),
+            2 => Port::<u16>::new(port).write(val as u16),
+            4 => Port::<u32>::new(port).write(val as u32),
+            other => return Err(AcpiError::UnsupportedAccessSize(other * 8)),
+        }
+    }
+    Ok(())
+}
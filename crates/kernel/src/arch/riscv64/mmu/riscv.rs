@@ -12,6 +12,28 @@ pub enum RiscvPagingError {
     WalkingHitInvalidPage,
 }
 
+impl core::fmt::Display for RiscvPagingError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            RiscvPagingError::AlreadyMapped {
+                attempted_ppn,
+                already_there_ppn,
+                vpn,
+            } => write!(
+                f,
+                "vpn {:#x} already maps ppn {:#x}, wanted {:#x}",
+                vpn, already_there_ppn, attempted_ppn
+            ),
+            RiscvPagingError::ReadInvalidPage => write!(f, "read an invalid page"),
+            RiscvPagingError::ReadReservedProtection(bits) => {
+                write!(f, "reserved protection bits {:#b}", bits)
+            }
+            RiscvPagingError::ReadNextTableFromLeaf => write!(f, "leaf used as a table"),
+            RiscvPagingError::WalkingHitInvalidPage => write!(f, "walk hit an invalid page"),
+        }
+    }
+}
+
 #[derive(PartialEq, Clone, Copy)]
 pub enum ProtectionBits {
     //0b0000
@@ -273,46 +295,42 @@ pub mod sv39_paging {
         allocator: &mut crate::heap_alloc::AndyAllocator<4096>,
         table: &mut Sv39,
     ) -> Result<(), <Sv39 as super::VirtualMemoryScheme>::MapError> {
-        let regions: [(usize, usize, super::ProtectionBits); 6] = unsafe {
-            [
-                (
-                    crate::arch::special::TEXT_START,
-                    crate::arch::special::TEXT_END,
-                    super::ProtectionBits::Execute,
-                ),
-                (
-                    crate::arch::special::RODATA_START,
-                    crate::arch::special::RODATA_END,
-                    super::ProtectionBits::Read,
-                ),
-                (
-                    crate::arch::special::DATA_START,
-                    crate::arch::special::DATA_END,
-                    super::ProtectionBits::ReadWrite,
-                ),
-                (
-                    crate::arch::special::BSS_START,
-                    crate::arch::special::BSS_END,
-                    super::ProtectionBits::ReadWrite,
-                ),
-                (
-                    crate::arch::special::STACK_BOT,
-                    crate::arch::special::STACK_TOP,
-                    super::ProtectionBits::ReadWrite,
-                ),
-                (
-                    crate::arch::special::HEAP_START,
-                    crate::arch::special::HEAP_END,
-                    super::ProtectionBits::ReadWrite,
-                ),
-            ]
-        };
-        let addrs = unsafe {
-            [
-                crate::arch::special::SYSCON_ADDR,
-                crate::arch::special::UART_ADDR,
-            ]
-        };
+        let regions: [(usize, usize, super::ProtectionBits); 6] = [
+            (
+                crate::arch::special::TEXT_START,
+                crate::arch::special::TEXT_END,
+                super::ProtectionBits::Execute,
+            ),
+            (
+                crate::arch::special::RODATA_START,
+                crate::arch::special::RODATA_END,
+                super::ProtectionBits::Read,
+            ),
+            (
+                crate::arch::special::DATA_START,
+                crate::arch::special::DATA_END,
+                super::ProtectionBits::ReadWrite,
+            ),
+            (
+                crate::arch::special::BSS_START,
+                crate::arch::special::BSS_END,
+                super::ProtectionBits::ReadWrite,
+            ),
+            (
+                crate::arch::special::STACK_BOT,
+                crate::arch::special::STACK_TOP,
+                super::ProtectionBits::ReadWrite,
+            ),
+            (
+                crate::arch::special::HEAP_START,
+                crate::arch::special::HEAP_END,
+                super::ProtectionBits::ReadWrite,
+            ),
+        ];
+        let addrs = [
+            crate::arch::special::SYSCON_ADDR,
+            crate::arch::special::UART_ADDR,
+        ];
 
         for region in regions {
             unsafe {
@@ -9,7 +9,13 @@ use mmu::VirtualMemoryScheme;
 use crate::kprintln;
 
 lazy_static::lazy_static! {
-    pub static ref WRITER: spin::Mutex<crate::uart::UartWriter> = spin::Mutex::new(unsafe { crate::uart::UartWriter::new(UART_ADDR) });
+    pub static ref WRITER: spin::Mutex<crate::uart::UartWriter<crate::uart::MmioSerial>> = {
+        let port = unsafe { crate::uart::MmioSerial::new(UART_ADDR) };
+        let mut writer = crate::uart::UartWriter::new(port, 115200);
+        //the trap handler echoes what comes in
+        writer.enable_receive_interrupt();
+        spin::Mutex::new(writer)
+    };
     pub static ref ALLOCATOR: spin::Mutex<crate::heap_alloc::AndyAllocator<4096>> = unsafe {spin::Mutex::new(crate::heap_alloc::AndyAllocator::new(HEAP_START, HEAP_END)) };
 }
 /*
@@ -64,20 +70,24 @@ static HEAP_END: usize = 0;
 static SYSCON_ADDR: usize = 0x00100000;
 static UART_ADDR: usize = 0x10000000;
 
-fn poweroff() {
+#[allow(dead_code)] //the syscon is there, nothing asks for it yet
+pub fn poweroff() -> ! {
     kprintln!("poweroff now");
     unsafe {
         let syscon_ptr: *mut u32 = crate::arch::special::SYSCON_ADDR as *mut u32;
         syscon_ptr.write_volatile(0x5555);
     }
+    abort()
 }
 
-fn reboot() {
+#[allow(dead_code)]
+pub fn reboot() -> ! {
     kprintln!("reboot now");
     unsafe {
         let syscon_ptr: *mut u32 = crate::arch::special::SYSCON_ADDR as *mut u32;
         syscon_ptr.write_volatile(0x7777);
     }
+    abort()
 }
 
 #[no_mangle]
@@ -94,10 +104,9 @@ pub fn kinit() {
         core::arch::asm!("csrw mscratch, {}", in(reg) trap_stack);
     }
 
-    let mut mem_table = mmu::riscv::sv39_paging::Sv39::new(&mut ALLOCATOR.lock()).unwrap();
+    let mut mem_table = mmu::sv39_paging::Sv39::new(&mut ALLOCATOR.lock()).unwrap();
 
-    mmu::riscv::sv39_paging::sv39_setup_identity_mapping(&mut ALLOCATOR.lock(), &mut mem_table)
-        .unwrap();
+    mmu::sv39_paging::sv39_setup_identity_mapping(&mut ALLOCATOR.lock(), &mut mem_table).unwrap();
 
     mmu::assert_identity_map(&mem_table);
 
@@ -116,6 +125,30 @@ pub fn kinit() {
     interrupt::set_priority(10, 1);
 }
 
+//s0 of whoever calls this, which only works inlined
+#[inline(always)]
+pub fn frame_pointer() -> usize {
+    let s0: usize;
+    unsafe { core::arch::asm!("mv {}, s0", out(reg) s0) };
+    s0
+}
+
+/// # Safety
+/// frame_pointer has to point right above a frame, which ends with the caller's s0 and then the
+/// return address
+pub unsafe fn unwind_frame(frame_pointer: usize) -> (usize, usize) {
+    let frame = frame_pointer as *const usize;
+    unsafe { (frame.sub(2).read(), frame.sub(1).read()) }
+}
+
+pub static SERIAL_INPUT: crate::uart::ReceiveBuffer = crate::uart::ReceiveBuffer::new();
+
+pub fn wait_for_input() {
+    if SERIAL_INPUT.is_empty() {
+        unsafe { core::arch::asm!("wfi") };
+    }
+}
+
 pub fn abort() -> ! {
     loop {
         unsafe {
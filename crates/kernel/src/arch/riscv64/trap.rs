@@ -1,5 +1,4 @@
 use super::csr_stuff;
-use crate::kprintln;
 
 #[derive(Debug)]
 enum InterruptExeption {
@@ -38,7 +37,7 @@ extern "C" fn rust_andy_trap(
     _mhart: usize,
     mstatus: usize,
 ) -> usize {
-    let _mstatus = csr_stuff::Mstatus::new(mstatus);
+    let mstatus = csr_stuff::Mstatus::new(mstatus);
 
     let from_interrupt: bool = ((mcause >> 63) & 1) == 1;
     let exeption_code = mcause & 0x7fffffff;
@@ -83,7 +82,7 @@ extern "C" fn rust_andy_trap(
             InterruptExeption::MExternal => machine_external_interrupt_handler(),
             _ => panic!("unhandled interrupt: {:?}", interrupt),
         },
-        int => panic!("unhandled exception: {:?}", int),
+        int => panic!("unhandled exception: {:?} from {:?} mode", int, mstatus.mpp),
     }
 
     mepc
@@ -93,14 +92,10 @@ fn machine_external_interrupt_handler() {
     if let Some(interrupt) = super::interrupt::next_interrupt() {
         match interrupt {
             10 => {
-                let maybe_byte = {
-                    let mut lock = crate::WRITER.try_lock().unwrap();
-                    lock.read_byte()
-                };
-                if let Some(byte) = maybe_byte {
-                    kprintln!("got byte {}", byte);
-                } else {
-                    panic!("sfwefefw");
+                //not through the writer, what this interrupted might be holding its lock
+                let mut port = unsafe { crate::uart::MmioSerial::new(super::UART_ADDR) };
+                while let Some(byte) = crate::uart::read_byte_from(&mut port) {
+                    super::SERIAL_INPUT.push(byte);
                 }
             }
             _ => todo!(),
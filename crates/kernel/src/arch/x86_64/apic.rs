@@ -0,0 +1,314 @@
+//the legacy 8259s, the local apic (xapic or x2apic) and the io apics from the madt
+use super::idt;
+use super::trap::TrapFrame;
+use crate::acpi::madt::{self, Madt, MadtEntry};
+use x86_64::instructions::port::Port;
+use x86_64::registers::model_specific::Msr;
+
+const IA32_APIC_BASE: u32 = 0x1b;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const APIC_BASE_X2APIC: u64 = 1 << 10;
+const APIC_BASE_ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+//local apic registers as xapic mmio offsets, x2apic has them at msr 0x800 + offset / 16
+const LAPIC_ID: u32 = 0x20;
+const LAPIC_TPR: u32 = 0x80;
+const LAPIC_EOI: u32 = 0xb0;
+const LAPIC_SVR: u32 = 0xf0;
+const LAPIC_ISR: u32 = 0x100; //8 of them, 0x10 apart
+const X2APIC_MSR_BASE: u32 = 0x800;
+const XAPIC_SIZE: u64 = 0x400;
+const SVR_ENABLE: u32 = 1 << 8;
+
+//a spurious interrupt isn't in service, so it never gets an eoi
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+//the 8259s are moved out of the way before they're masked, a spurious irq 7 or 15 can still come in.
+//below the spurious vector, which the slave's irq 15 would land on from 0xf0
+const PIC_VECTOR_BASE: u8 = 0xe0;
+
+//io apic redirection entries
+const IOAPIC_REGSEL: u64 = 0x00;
+const IOAPIC_WINDOW: u64 = 0x10;
+const IOAPIC_VERSION: u32 = 0x01;
+const IOAPIC_REDIRECTION_TABLE: u32 = 0x10;
+const REDIRECTION_ACTIVE_LOW: u64 = 1 << 13;
+const REDIRECTION_LEVEL: u64 = 1 << 15;
+const REDIRECTION_MASKED: u64 = 1 << 16;
+
+const MAX_IO_APICS: usize = 8;
+const NUM_ISA_IRQS: usize = 16;
+
+enum LocalApicMode {
+    //the registers' virtual address
+    XApic(u64),
+    X2Apic,
+}
+
+pub struct LocalApic {
+    mode: LocalApicMode,
+}
+
+impl LocalApic {
+    fn read(&self, reg: u32) -> u32 {
+        match self.mode {
+            LocalApicMode::XApic(base) => unsafe {
+                ((base + reg as u64) as *const u32).read_volatile()
+            },
+            LocalApicMode::X2Apic => unsafe {
+                Msr::new(X2APIC_MSR_BASE + (reg >> 4)).read() as u32
+            },
+        }
+    }
+
+    fn write(&self, reg: u32, val: u32) {
+        match self.mode {
+            LocalApicMode::XApic(base) => unsafe {
+                ((base + reg as u64) as *mut u32).write_volatile(val)
+            },
+            LocalApicMode::X2Apic => unsafe {
+                Msr::new(X2APIC_MSR_BASE + (reg >> 4)).write(val as u64)
+            },
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        match self.mode {
+            LocalApicMode::XApic(_) => self.read(LAPIC_ID) >> 24,
+            LocalApicMode::X2Apic => self.read(LAPIC_ID),
+        }
+    }
+
+    //only vectors in a higher priority class than this get through
+    pub fn set_task_priority(&self, class: u8) {
+        self.write(LAPIC_TPR, (class as u32 & 0xf) << 4);
+    }
+
+    //the highest priority vector being handled, what an eoi would finish
+    pub fn in_service_vector(&self) -> Option<u8> {
+        (0..8u32).rev().find_map(|i| {
+            let bits = self.read(LAPIC_ISR + i * 0x10);
+            (bits != 0).then(|| (i * 32 + 31 - bits.leading_zeros()) as u8)
+        })
+    }
+
+    pub fn eoi(&self) {
+        self.write(LAPIC_EOI, 0);
+    }
+}
+
+pub struct IoApic {
+    //the registers' virtual address
+    base: u64,
+    pub gsi_base: u32,
+    pub num_entries: u32,
+}
+
+impl IoApic {
+    fn read(&self, reg: u32) -> u32 {
+        unsafe {
+            ((self.base + IOAPIC_REGSEL) as *mut u32).write_volatile(reg);
+            ((self.base + IOAPIC_WINDOW) as *const u32).read_volatile()
+        }
+    }
+
+    fn write(&self, reg: u32, val: u32) {
+        unsafe {
+            ((self.base + IOAPIC_REGSEL) as *mut u32).write_volatile(reg);
+            ((self.base + IOAPIC_WINDOW) as *mut u32).write_volatile(val);
+        }
+    }
+
+    fn handles(&self, gsi: u32) -> bool {
+        (self.gsi_base..self.gsi_base + self.num_entries).contains(&gsi)
+    }
+
+    //masked first, so the entry is never live half written
+    fn set_redirection(&self, gsi: u32, entry: u64) {
+        let reg = IOAPIC_REDIRECTION_TABLE + 2 * (gsi - self.gsi_base);
+        self.write(reg, REDIRECTION_MASKED as u32);
+        self.write(reg + 1, (entry >> 32) as u32);
+        self.write(reg, entry as u32);
+    }
+}
+
+//where an isa irq really comes in, from the madt's interrupt source overrides
+#[derive(Clone, Copy)]
+struct SourceOverride {
+    gsi: u32,
+    flags: u16,
+}
+
+struct IoApics {
+    apics: [Option<IoApic>; MAX_IO_APICS],
+    overrides: [Option<SourceOverride>; NUM_ISA_IRQS],
+}
+
+static LOCAL_APIC: spin::Once<LocalApic> = spin::Once::new();
+static IO_APICS: spin::Once<IoApics> = spin::Once::new();
+
+pub fn local() -> &'static LocalApic {
+    LOCAL_APIC.get().expect("local apic not set up yet")
+}
+
+//after idt::init and acpi::init. without a madt there are no io apics, only the local apic
+pub fn init() {
+    let madt = Madt::get();
+
+    //the madt has a flag for these but firmware gets it wrong, masking ones that aren't there does nothing
+    unsafe { disable_8259() };
+    for vector in [PIC_VECTOR_BASE + 7, PIC_VECTOR_BASE + 15, SPURIOUS_VECTOR] {
+        if let Err(err) = idt::register_handler(vector, spurious_handler) {
+            crate::kprintln!("not catching spurious interrupts: {}", err);
+        }
+    }
+
+    let local_apic = LOCAL_APIC.call_once(|| unsafe { enable_local_apic() });
+
+    let mut io_apics = IoApics {
+        apics: [const { None }; MAX_IO_APICS],
+        overrides: [None; NUM_ISA_IRQS],
+    };
+    if let Some(madt) = madt {
+        let mut next_apic = 0;
+        let mut num_cpus = 0;
+        for entry in madt.entries() {
+            match entry {
+                MadtEntry::LocalApic { flags } | MadtEntry::LocalX2Apic { flags }
+                    if flags & (madt::CPU_ENABLED | madt::CPU_ONLINE_CAPABLE) != 0 =>
+                {
+                    num_cpus += 1;
+                }
+                MadtEntry::IoApic { addr, gsi_base, .. } if next_apic < MAX_IO_APICS => {
+                    let base = super::map_mmio(addr as u64, IOAPIC_WINDOW + 4);
+                    let mut io_apic = IoApic {
+                        base,
+                        gsi_base,
+                        num_entries: 0,
+                    };
+                    io_apic.num_entries = ((io_apic.read(IOAPIC_VERSION) >> 16) & 0xff) + 1;
+                    for gsi in gsi_base..gsi_base + io_apic.num_entries {
+                        io_apic.set_redirection(gsi, REDIRECTION_MASKED);
+                    }
+                    io_apics.apics[next_apic] = Some(io_apic);
+                    next_apic += 1;
+                }
+                //bus 0 is isa, the only one overrides are for
+                MadtEntry::InterruptSourceOverride {
+                    bus: 0,
+                    source,
+                    gsi,
+                    flags,
+                } if (source as usize) < NUM_ISA_IRQS => {
+                    io_apics.overrides[source as usize] = Some(SourceOverride { gsi, flags });
+                }
+                _ => {}
+            }
+        }
+        crate::kprintln!("{} cpus, {} io apics", num_cpus, next_apic);
+    }
+    IO_APICS.call_once(|| io_apics);
+
+    //everything through until interrupt::set_threshold says otherwise
+    local_apic.set_task_priority(0);
+}
+
+//the io apic id comes in on, with its gsi and mps flags. ids below 16 are isa irqs and go through
+//the overrides, the rest are gsis
+fn find_route(id: u32) -> Option<(&'static IoApic, u32, u16)> {
+    let io_apics = IO_APICS.get()?;
+
+    //isa irqs are edge triggered and active high unless overridden
+    let (gsi, flags) = match io_apics.overrides.get(id as usize) {
+        Some(Some(over)) => (over.gsi, over.flags),
+        _ => (id, 0),
+    };
+    let io_apic = io_apics
+        .apics
+        .iter()
+        .flatten()
+        .find(|apic| apic.handles(gsi))?;
+    Some((io_apic, gsi, flags))
+}
+
+//without a madt nothing does
+pub fn has_route(id: u32) -> bool {
+    find_route(id).is_some()
+}
+
+//sends id to vector on this cpu, false if no io apic has it
+pub fn route(id: u32, vector: u8, masked: bool) -> bool {
+    let Some((io_apic, gsi, flags)) = find_route(id) else {
+        return false;
+    };
+
+    //pci interrupts past the isa ones are level triggered and active low
+    let (active_low, level) = if id as usize >= NUM_ISA_IRQS && flags == 0 {
+        (true, true)
+    } else {
+        (
+            flags & madt::MPS_POLARITY_MASK == madt::MPS_POLARITY_ACTIVE_LOW,
+            flags & madt::MPS_TRIGGER_MASK == madt::MPS_TRIGGER_LEVEL,
+        )
+    };
+
+    //physical destination mode only has 8 bits, x2apic ids past that would need interrupt remapping
+    let destination = local().id() as u64 & 0xff;
+    let mut entry = vector as u64 | destination << 56;
+    if active_low {
+        entry |= REDIRECTION_ACTIVE_LOW;
+    }
+    if level {
+        entry |= REDIRECTION_LEVEL;
+    }
+    if masked {
+        entry |= REDIRECTION_MASKED;
+    }
+    io_apic.set_redirection(gsi, entry);
+    true
+}
+
+/// # Safety
+/// only once, with the 8259s not in use
+unsafe fn disable_8259() {
+    let mut master_command = Port::<u8>::new(0x20);
+    let mut master_data = Port::<u8>::new(0x21);
+    let mut slave_command = Port::<u8>::new(0xa0);
+    let mut slave_data = Port::<u8>::new(0xa1);
+    unsafe {
+        //icw1 to icw4: start, vector base, how they're cascaded, 8086 mode
+        master_command.write(0x11);
+        slave_command.write(0x11);
+        master_data.write(PIC_VECTOR_BASE);
+        slave_data.write(PIC_VECTOR_BASE + 8);
+        master_data.write(1 << 2); //slave on irq 2
+        slave_data.write(2);
+        master_data.write(0x01);
+        slave_data.write(0x01);
+
+        master_data.write(0xff);
+        slave_data.write(0xff);
+    }
+}
+
+/// # Safety
+/// only once
+unsafe fn enable_local_apic() -> LocalApic {
+    let has_x2apic = core::arch::x86_64::__cpuid(1).ecx & (1 << 21) != 0;
+
+    let mut apic_base = Msr::new(IA32_APIC_BASE);
+    let base = unsafe { apic_base.read() };
+    //x2apic can only be turned on from an enabled xapic
+    unsafe { apic_base.write(base | APIC_BASE_ENABLE) };
+    let mode = if has_x2apic {
+        unsafe { apic_base.write(base | APIC_BASE_ENABLE | APIC_BASE_X2APIC) };
+        LocalApicMode::X2Apic
+    } else {
+        LocalApicMode::XApic(super::map_mmio(base & APIC_BASE_ADDR_MASK, XAPIC_SIZE))
+    };
+
+    let local_apic = LocalApic { mode };
+    local_apic.write(LAPIC_SVR, SVR_ENABLE | SPURIOUS_VECTOR as u32);
+    local_apic
+}
+
+fn spurious_handler(_vector: u8, _frame: &mut TrapFrame) {}
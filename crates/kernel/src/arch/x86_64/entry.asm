@@ -8,9 +8,9 @@ kentry:
 	mov al, 0x49
 	out 0xe9, al
 	
-	//mov stack_top, esp
-
-	//call kinit
+	/* rdi already holds the boot info pointer from the bootloader. kinit moves on to kmain on its
+	   own stack and never comes back here */
+	call kinit
 
 	cli
 1:	hlt
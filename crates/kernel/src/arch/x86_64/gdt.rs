@@ -0,0 +1,78 @@
+use x86_64::instructions::segmentation::{Segment, CS, DS, ES, SS};
+use x86_64::instructions::tables::load_tss;
+use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
+use x86_64::structures::tss::TaskStateSegment;
+use x86_64::VirtAddr;
+
+//exceptions that can happen on a broken stack get a known good one from the tss
+pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+pub const NMI_IST_INDEX: u16 = 1;
+pub const MACHINE_CHECK_IST_INDEX: u16 = 2;
+
+const IST_STACK_SIZE: usize = 5 * 4096;
+//the bootloader's stack is only as big as the config says, so the kernel brings its own
+pub const KERNEL_STACK_SIZE: usize = 16 * 4096;
+
+#[repr(C, align(16))]
+struct Stack<const SIZE: usize>([u8; SIZE]);
+
+static mut DOUBLE_FAULT_STACK: Stack<IST_STACK_SIZE> = Stack([0; IST_STACK_SIZE]);
+static mut NMI_STACK: Stack<IST_STACK_SIZE> = Stack([0; IST_STACK_SIZE]);
+static mut MACHINE_CHECK_STACK: Stack<IST_STACK_SIZE> = Stack([0; IST_STACK_SIZE]);
+static mut KERNEL_STACK: Stack<KERNEL_STACK_SIZE> = Stack([0; KERNEL_STACK_SIZE]);
+
+//stacks grow down, so this is the end
+fn stack_top<const SIZE: usize>(stack: *const Stack<SIZE>) -> VirtAddr {
+    VirtAddr::from_ptr(stack) + SIZE as u64
+}
+
+pub fn kernel_stack_top() -> VirtAddr {
+    stack_top(core::ptr::addr_of!(KERNEL_STACK))
+}
+
+struct Selectors {
+    kernel_code: SegmentSelector,
+    kernel_data: SegmentSelector,
+    tss: SegmentSelector,
+}
+
+lazy_static::lazy_static! {
+    static ref TSS: TaskStateSegment = {
+        let mut tss = TaskStateSegment::new();
+        //rsp0 stays 0 until there's a user mode, kmain's stack is live and can't take traps from ring 3
+        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+            stack_top(core::ptr::addr_of!(DOUBLE_FAULT_STACK));
+        tss.interrupt_stack_table[NMI_IST_INDEX as usize] = stack_top(core::ptr::addr_of!(NMI_STACK));
+        tss.interrupt_stack_table[MACHINE_CHECK_IST_INDEX as usize] =
+            stack_top(core::ptr::addr_of!(MACHINE_CHECK_STACK));
+        tss
+    };
+    static ref GDT: (GlobalDescriptorTable, Selectors) = {
+        let mut gdt = GlobalDescriptorTable::new();
+        let kernel_code = gdt.append(Descriptor::kernel_code_segment());
+        let kernel_data = gdt.append(Descriptor::kernel_data_segment());
+        let tss = gdt.append(Descriptor::tss_segment(&TSS));
+        (
+            gdt,
+            Selectors {
+                kernel_code,
+                kernel_data,
+                tss,
+            },
+        )
+    };
+}
+
+//replaces the firmware's gdt, which goes away with the rest of boot services memory
+pub fn init() {
+    let (gdt, selectors) = &*GDT;
+    gdt.load();
+    unsafe {
+        CS::set_reg(selectors.kernel_code);
+        SS::set_reg(selectors.kernel_data);
+        DS::set_reg(selectors.kernel_data);
+        ES::set_reg(selectors.kernel_data);
+        //not fs or gs, loading a selector wipes the fs base the bootloader pointed at the tls block
+        load_tss(selectors.tss);
+    }
+}
@@ -0,0 +1,121 @@
+use super::gdt;
+use super::trap::{self, TrapFrame};
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use x86_64::instructions::segmentation::{Segment, CS};
+use x86_64::instructions::tables::{lidt, DescriptorTablePointer};
+use x86_64::VirtAddr;
+
+//everything below is a cpu exception
+pub const FIRST_DEVICE_VECTOR: u8 = 32;
+
+const GATE_INTERRUPT: u8 = 0x8e; //present, ring 0, interrupts off while handling
+
+pub type InterruptHandler = fn(vector: u8, frame: &mut TrapFrame);
+
+#[derive(Debug)]
+pub enum RegisterError {
+    Exception(u8),
+    InUse(u8),
+}
+
+impl fmt::Display for RegisterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegisterError::Exception(vector) => write!(f, "vector {} is an exception", vector),
+            RegisterError::InUse(vector) => write!(f, "vector {} already has a handler", vector),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct IdtEntry {
+    offset_low: u16,
+    selector: u16,
+    //ist index + 1, 0 stays on the current stack
+    ist: u8,
+    type_attr: u8,
+    offset_mid: u16,
+    offset_high: u32,
+    reserved: u32,
+}
+
+impl IdtEntry {
+    fn new(handler: u64, selector: u16, ist: Option<u16>) -> Self {
+        IdtEntry {
+            offset_low: handler as u16,
+            selector,
+            ist: ist.map_or(0, |index| index as u8 + 1),
+            type_attr: GATE_INTERRUPT,
+            offset_mid: (handler >> 16) as u16,
+            offset_high: (handler >> 32) as u32,
+            reserved: 0,
+        }
+    }
+}
+
+#[repr(C, align(16))]
+struct Idt([IdtEntry; 256]);
+
+lazy_static::lazy_static! {
+    static ref IDT: Idt = {
+        let selector = CS::get_reg().0;
+        Idt(core::array::from_fn(|vector| {
+            let vector = vector as u8;
+            //these can come in on a broken stack
+            let ist = match vector {
+                8 => Some(gdt::DOUBLE_FAULT_IST_INDEX),
+                trap::NMI => Some(gdt::NMI_IST_INDEX),
+                18 => Some(gdt::MACHINE_CHECK_IST_INDEX),
+                _ => None,
+            };
+            IdtEntry::new(trap::trap_stub_addr(vector), selector, ist)
+        }))
+    };
+}
+
+//fn pointers as usizes, 0 for nothing registered. atomics so the trap path never waits on a lock
+static HANDLERS: [AtomicUsize; 256] = [const { AtomicUsize::new(0) }; 256];
+
+//after gdt::init, the entries take the kernel's code segment
+pub fn init() {
+    let idt: &'static Idt = &IDT;
+    let pointer = DescriptorTablePointer {
+        limit: (core::mem::size_of::<Idt>() - 1) as u16,
+        base: VirtAddr::from_ptr(idt),
+    };
+    unsafe { lidt(&pointer) };
+}
+
+pub fn register_handler(vector: u8, handler: InterruptHandler) -> Result<(), RegisterError> {
+    if vector < FIRST_DEVICE_VECTOR {
+        return Err(RegisterError::Exception(vector));
+    }
+    HANDLERS[vector as usize]
+        .compare_exchange(0, handler as usize, Ordering::AcqRel, Ordering::Acquire)
+        .map(|_| ())
+        .map_err(|_| RegisterError::InUse(vector))
+}
+
+pub fn unregister_handler(vector: u8) {
+    HANDLERS[vector as usize].store(0, Ordering::Release);
+}
+
+//the first free device vector, for devices that don't care which one they get. the irqs behind
+//interrupt pick theirs by priority instead
+#[allow(dead_code)]
+pub fn allocate_vector(handler: InterruptHandler) -> Option<u8> {
+    (FIRST_DEVICE_VECTOR..=u8::MAX).find(|&vector| register_handler(vector, handler).is_ok())
+}
+
+//false if nothing is registered for vector
+pub fn dispatch(vector: u8, frame: &mut TrapFrame) -> bool {
+    let handler = HANDLERS[vector as usize].load(Ordering::Acquire);
+    if handler == 0 {
+        return false;
+    }
+    let handler: InterruptHandler = unsafe { core::mem::transmute(handler) };
+    handler(vector, frame);
+    true
+}
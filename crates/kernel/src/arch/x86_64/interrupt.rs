@@ -0,0 +1,96 @@
+//the same interface as riscv64's plic, in front of the io apics and the local apic.
+//ids are isa irqs below 16 and gsis past that. a priority picks the priority class of the
+//id's vector, so the local apic's task priority works as the threshold the way the plic's does
+use super::apic;
+use super::idt;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+//priority 0 lands in the first class past the exceptions, which a threshold of 0 already blocks
+const FIRST_PRIORITY_CLASS: u8 = idt::FIRST_DEVICE_VECTOR >> 4;
+const MAX_IDS: usize = 256;
+const NO_ID: u32 = u32::MAX;
+
+#[derive(Clone, Copy)]
+struct Route {
+    vector: Option<u8>,
+    priority: u8,
+    enabled: bool,
+}
+
+static ROUTES: spin::Mutex<[Route; MAX_IDS]> = spin::Mutex::new(
+    [Route {
+        vector: None,
+        priority: 0,
+        enabled: false,
+    }; MAX_IDS],
+);
+
+//vector to id, atomics so claiming never waits on a lock
+static VECTOR_IDS: [AtomicU32; 256] = [const { AtomicU32::new(NO_ID) }; 256];
+
+pub fn enable(id: u32) {
+    update_route(id, |route| route.enabled = true);
+}
+
+pub fn set_priority(id: u32, prio: u8) {
+    assert!(prio < 8);
+
+    update_route(id, |route| route.priority = prio);
+}
+
+pub fn set_threshold(threshold: u8) {
+    assert!(threshold < 8);
+
+    apic::local().set_task_priority(FIRST_PRIORITY_CLASS + threshold);
+}
+
+//the id of the vector being handled, the local apic already claimed it when it was delivered
+pub fn next_interrupt() -> Option<u32> {
+    let vector = apic::local().in_service_vector()?;
+
+    match VECTOR_IDS[vector as usize].load(Ordering::Acquire) {
+        NO_ID => None,
+        id => Some(id),
+    }
+}
+
+//an eoi always finishes the highest priority vector in service, which is id's
+pub fn complete(_id: u32) {
+    apic::local().eoi();
+}
+
+fn update_route(id: u32, f: impl FnOnce(&mut Route)) {
+    assert!((id as usize) < MAX_IDS);
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut routes = ROUTES.lock();
+        let route = &mut routes[id as usize];
+        f(route);
+
+        //a vector from the new class if the priority changed
+        let class = FIRST_PRIORITY_CLASS + route.priority;
+        let vector = match route.vector {
+            Some(vector) if vector >> 4 == class => vector,
+            old => {
+                if let Some(old) = old {
+                    idt::unregister_handler(old);
+                    VECTOR_IDS[old as usize].store(NO_ID, Ordering::Release);
+                }
+                let vector = (class << 4..=class << 4 | 0xf)
+                    .find(|&vector| {
+                        idt::register_handler(vector, super::trap::external_interrupt_handler)
+                            .is_ok()
+                    })
+                    .expect("no free vectors for the priority");
+                VECTOR_IDS[vector as usize].store(id, Ordering::Release);
+                route.vector = Some(vector);
+                vector
+            }
+        };
+
+        //callers check apic::has_route first
+        if !apic::route(id, vector, !route.enabled) {
+            panic!("no io apic has interrupt {}", id);
+        }
+    });
+}
@@ -0,0 +1,316 @@
+//4 level paging, with the tables reached through the bootloader's physical memory map
+use crate::heap_alloc::AndyAllocator;
+use crate::mmu::{PhysicalAddr, VirtualAddr, VirtualMemoryScheme};
+use core::mem::size_of;
+use static_assertions::const_assert;
+
+const PAGE_SIZE_BYTES: usize = 4096;
+const LEVELS: usize = 4;
+const ENTRIES: usize = 512;
+//the pml4 entries from here on are the kernel's half
+const KERNEL_HALF_START: usize = ENTRIES / 2;
+
+const PRESENT: u64 = 1 << 0;
+const WRITABLE: u64 = 1 << 1;
+const USER: u64 = 1 << 2;
+const WRITE_THROUGH: u64 = 1 << 3;
+const CACHE_DISABLE: u64 = 1 << 4;
+//set by the cpu, not part of what a mapping asked for
+const ACCESSED: u64 = 1 << 5;
+const DIRTY: u64 = 1 << 6;
+const HUGE_PAGE: u64 = 1 << 7;
+const GLOBAL: u64 = 1 << 8;
+const NO_EXECUTE: u64 = 1 << 63;
+const ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+const_assert!(size_of::<PageTableEntry>() == size_of::<u64>());
+const_assert!(size_of::<PageTable>() == PAGE_SIZE_BYTES);
+
+#[derive(Debug)]
+pub enum X86PagingError {
+    AlreadyMapped {
+        attempted_ppn: usize,
+        already_there_ppn: usize,
+        vpn: usize,
+    },
+    //a 2MiB or 1GiB page is in the way of the 4KiB one
+    HugePageInTheWay(usize),
+    NonCanonical(usize),
+    WalkingHitInvalidPage,
+    OutOfMemory,
+}
+
+impl core::fmt::Display for X86PagingError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            X86PagingError::AlreadyMapped {
+                attempted_ppn,
+                already_there_ppn,
+                vpn,
+            } => write!(
+                f,
+                "page {:#x} already maps frame {:#x}, not {:#x}",
+                vpn, already_there_ppn, attempted_ppn
+            ),
+            X86PagingError::HugePageInTheWay(vpn) => {
+                write!(f, "a huge page is in the way of page {:#x}", vpn)
+            }
+            X86PagingError::NonCanonical(addr) => write!(f, "{:#x} isn't canonical", addr),
+            X86PagingError::WalkingHitInvalidPage => write!(f, "not mapped"),
+            X86PagingError::OutOfMemory => write!(f, "out of memory for page tables"),
+        }
+    }
+}
+
+//reads are always allowed, there's no way to map a page without them
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct PageFlags {
+    pub write: bool,
+    pub execute: bool,
+    pub user: bool,
+    //stays in the tlb across cr3 writes
+    pub global: bool,
+    pub write_through: bool,
+    pub cache_disable: bool,
+}
+
+impl PageFlags {
+    pub const READ: PageFlags = PageFlags {
+        write: false,
+        execute: false,
+        user: false,
+        global: false,
+        write_through: false,
+        cache_disable: false,
+    };
+    //for device registers
+    pub const MMIO: PageFlags = PageFlags {
+        write: true,
+        write_through: true,
+        cache_disable: true,
+        ..PageFlags::READ
+    };
+
+    fn bits(&self) -> u64 {
+        let mut bits = PRESENT;
+        if self.write {
+            bits |= WRITABLE;
+        }
+        if !self.execute {
+            bits |= NO_EXECUTE;
+        }
+        if self.user {
+            bits |= USER;
+        }
+        if self.global {
+            bits |= GLOBAL;
+        }
+        if self.write_through {
+            bits |= WRITE_THROUGH;
+        }
+        if self.cache_disable {
+            bits |= CACHE_DISABLE;
+        }
+        bits
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PageTableEntry {
+    bits: u64,
+}
+
+impl PageTableEntry {
+    fn is_present(&self) -> bool {
+        self.bits & PRESENT != 0
+    }
+    fn is_huge(&self) -> bool {
+        self.bits & HUGE_PAGE != 0
+    }
+    fn addr(&self) -> u64 {
+        self.bits & ADDR_MASK
+    }
+}
+
+#[repr(C, align(4096))]
+#[derive(Clone, Copy)]
+struct PageTable {
+    entries: [PageTableEntry; ENTRIES],
+}
+
+pub struct Pml4 {
+    //physical, what goes in cr3
+    root: u64,
+    physical_memory_offset: u64,
+}
+
+fn get_vpn_index(vpn: usize, level: usize) -> usize {
+    assert!(level < LEVELS);
+    (vpn >> (9 * level)) & (ENTRIES - 1)
+}
+
+//the sign extension above bit 47 has to match bit 47
+fn is_canonical(addr: usize) -> bool {
+    ((addr << 16) as isize >> 16) as usize == addr
+}
+
+impl Pml4 {
+    fn table(&self, phys: u64) -> *mut PageTable {
+        (self.physical_memory_offset + phys) as *mut PageTable
+    }
+
+    //a zeroed page from the allocator, and its physical address
+    fn new_table(
+        allocator: &mut AndyAllocator<PAGE_SIZE_BYTES>,
+        physical_memory_offset: u64,
+    ) -> Result<u64, X86PagingError> {
+        let page = allocator
+            .allocate(1)
+            .map_err(|_| X86PagingError::OutOfMemory)?;
+        unsafe { (page as *mut u8).write_bytes(0, PAGE_SIZE_BYTES) };
+        Ok(page as u64 - physical_memory_offset)
+    }
+
+    //the root of the tables the cpu is using now
+    pub fn active_root() -> u64 {
+        let cr3: u64;
+        unsafe { core::arch::asm!("mov {}, cr3", out(reg) cr3) };
+        cr3 & ADDR_MASK
+    }
+
+    /// # Safety
+    /// from_root has to be a pml4 reached through the same physical memory offset, and has to
+    /// stay as it is while it's copied
+    pub unsafe fn copy_kernel_half(
+        &mut self,
+        allocator: &mut AndyAllocator<PAGE_SIZE_BYTES>,
+        from_root: u64,
+    ) -> Result<(), X86PagingError> {
+        let to = self.table(self.root);
+        for index in KERNEL_HALF_START..ENTRIES {
+            unsafe {
+                (*to).entries[index] = self.copy_entry(allocator, from_root, index, LEVELS - 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    //leaves are shared with from, only the tables under it are new
+    unsafe fn copy_entry(
+        &mut self,
+        allocator: &mut AndyAllocator<PAGE_SIZE_BYTES>,
+        from_table: u64,
+        index: usize,
+        level: usize,
+    ) -> Result<PageTableEntry, X86PagingError> {
+        let entry = unsafe { (*self.table(from_table)).entries[index] };
+        if !entry.is_present() || level == 0 || entry.is_huge() {
+            return Ok(entry);
+        }
+
+        let new_table = Self::new_table(allocator, self.physical_memory_offset)?;
+        for child in 0..ENTRIES {
+            let copied = unsafe { self.copy_entry(allocator, entry.addr(), child, level - 1)? };
+            unsafe { (*self.table(new_table)).entries[child] = copied };
+        }
+        Ok(PageTableEntry {
+            bits: (entry.bits & !ADDR_MASK) | new_table,
+        })
+    }
+}
+
+impl VirtualMemoryScheme for Pml4 {
+    type MapError = X86PagingError;
+    type MapProtection = PageFlags;
+
+    fn new(allocator: &mut AndyAllocator<PAGE_SIZE_BYTES>) -> Result<Self, Self::MapError> {
+        let physical_memory_offset = super::boot_info().physical_memory_offset;
+        let root = Self::new_table(allocator, physical_memory_offset)?;
+        Ok(Pml4 {
+            root,
+            physical_memory_offset,
+        })
+    }
+
+    //virtual_page_num is the address over 4096 without the sign extension, so under 2^36
+    unsafe fn create_mapping(
+        &mut self,
+        allocator: &mut AndyAllocator<PAGE_SIZE_BYTES>,
+        virtual_page_num: usize,
+        physical_page_num: usize,
+        protection: Self::MapProtection,
+    ) -> Result<(), Self::MapError> {
+        assert!(virtual_page_num < (1 << 36));
+        let mut curr_table = self.root;
+        for level in (0..LEVELS).rev() {
+            let vpn = get_vpn_index(virtual_page_num, level);
+            let entry = unsafe { &mut (*self.table(curr_table)).entries[vpn] };
+            if level == 0 {
+                let bits = protection.bits() | (physical_page_num * PAGE_SIZE_BYTES) as u64;
+                if entry.is_present() {
+                    //mapping a page again exactly the same way is fine, so mmio regions can share one
+                    if entry.bits & !(ACCESSED | DIRTY) == bits {
+                        return Ok(());
+                    }
+                    return Err(X86PagingError::AlreadyMapped {
+                        attempted_ppn: physical_page_num,
+                        already_there_ppn: (entry.addr() / PAGE_SIZE_BYTES as u64) as usize,
+                        vpn: virtual_page_num,
+                    });
+                }
+                entry.bits = bits;
+                return Ok(());
+            }
+
+            if entry.is_present() {
+                if entry.is_huge() {
+                    return Err(X86PagingError::HugePageInTheWay(virtual_page_num));
+                }
+            } else {
+                let new_table = Self::new_table(allocator, self.physical_memory_offset)?;
+                entry.bits = new_table | PRESENT | WRITABLE;
+            }
+            //the leaf decides, but a user page needs user tables all the way down
+            if protection.user {
+                entry.bits |= USER;
+            }
+            curr_table = entry.addr();
+        }
+
+        unreachable!()
+    }
+
+    fn find_map(&self, from: VirtualAddr) -> Result<PhysicalAddr, Self::MapError> {
+        if !is_canonical(from.0) {
+            return Err(X86PagingError::NonCanonical(from.0));
+        }
+        let vpn = (from.0 >> 12) & ((1 << 36) - 1);
+        let mut curr_table = self.root;
+        for level in (0..LEVELS).rev() {
+            let entry = unsafe { (*self.table(curr_table)).entries[get_vpn_index(vpn, level)] };
+            if !entry.is_present() {
+                return Err(X86PagingError::WalkingHitInvalidPage);
+            }
+            if level == 0 || entry.is_huge() {
+                //4KiB, 2MiB or 1GiB of offset
+                let page_offset_mask = (PAGE_SIZE_BYTES << (9 * level)) - 1;
+                let addr = entry.addr() as usize & !page_offset_mask;
+                return Ok(PhysicalAddr(addr | (from.0 & page_offset_mask)));
+            }
+            curr_table = entry.addr();
+        }
+
+        unreachable!()
+    }
+
+    unsafe fn activate(&self) -> Result<(), Self::MapError> {
+        use x86_64::registers::control::{Cr4, Cr4Flags};
+
+        unsafe {
+            Cr4::update(|cr4| *cr4 |= Cr4Flags::PAGE_GLOBAL);
+            core::arch::asm!("mov cr3, {}", in(reg) self.root);
+        }
+        Ok(())
+    }
+}
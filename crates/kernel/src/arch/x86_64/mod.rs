@@ -1,8 +1,55 @@
+pub mod apic;
 pub mod entry;
+pub mod gdt;
+pub mod idt;
+pub mod interrupt;
+pub mod mmu;
+pub mod trap;
+
+use crate::mmu::VirtualMemoryScheme;
+
+const COM1: u16 = 0x3f8;
+const COM1_IRQ: u32 = 4;
 
 lazy_static::lazy_static! {
-    pub static ref WRITER: spin::Mutex<crate::uart::UartWriter> = todo!();
-    pub static ref ALLOCATOR: spin::Mutex<crate::heap_alloc::AndyAllocator<4096>> = todo!();
+    pub static ref WRITER: spin::Mutex<crate::uart::UartWriter<crate::uart::PortSerial>> = {
+        let port = unsafe { crate::uart::PortSerial::new(COM1) };
+        spin::Mutex::new(crate::uart::UartWriter::new(port, 115200))
+    };
+    pub static ref ALLOCATOR: spin::Mutex<crate::heap_alloc::AndyAllocator<4096>> = {
+        let (heap_start, heap_end) = largest_usable_region();
+        unsafe { spin::Mutex::new(crate::heap_alloc::AndyAllocator::new(heap_start, heap_end)) }
+    };
+}
+
+//through the bootloader's physical memory map, so the allocator hands out usable virtual addresses
+fn largest_usable_region() -> (usize, usize) {
+    let boot_info = boot_info();
+    let region = boot_info
+        .memory_regions()
+        .iter()
+        .filter(|region| region.kind == boot_info::MemoryRegionKind::Usable)
+        .max_by_key(|region| region.end - region.start)
+        .expect("no usable memory");
+    let offset = boot_info.physical_memory_offset;
+    (
+        (offset + region.start) as usize,
+        (offset + region.end) as usize,
+    )
+}
+
+//what the serial port's receive interrupt drained
+pub static SERIAL_INPUT: crate::uart::ReceiveBuffer = crate::uart::ReceiveBuffer::new();
+
+//interrupts stay off between checking and halting, sti only lets them in after the hlt, so
+//input coming in right then still wakes it up
+pub fn wait_for_input() {
+    x86_64::instructions::interrupts::disable();
+    if SERIAL_INPUT.is_empty() {
+        x86_64::instructions::interrupts::enable_and_hlt();
+    } else {
+        x86_64::instructions::interrupts::enable();
+    }
 }
 
 pub fn abort() -> ! {
@@ -14,7 +61,181 @@ pub fn abort() -> ! {
     }
 }
 
+//inlined, so this is the caller's frame and not one of its own
+#[inline(always)]
+pub fn frame_pointer() -> usize {
+    let rbp: usize;
+    unsafe { core::arch::asm!("mov {}, rbp", out(reg) rbp) };
+    rbp
+}
+
+/// # Safety
+/// frame_pointer has to point at a frame, which is the caller's rbp and then the return address
+pub unsafe fn unwind_frame(frame_pointer: usize) -> (usize, usize) {
+    let frame = frame_pointer as *const usize;
+    unsafe { (frame.read(), frame.add(1).read()) }
+}
+
+//no syscon like on riscv: acpi first, then the firmware. falls back to stopping if neither works
+#[allow(dead_code)] //no caller yet, the kernel has no shell or power button handling
+pub fn poweroff() -> ! {
+    x86_64::instructions::interrupts::disable();
+    let err = crate::acpi::fadt::poweroff();
+    crate::kprintln!("acpi poweroff didn't work: {}", err);
+    crate::uefi_runtime::reset_system(crate::uefi_runtime::ResetType::Shutdown);
+    abort()
+}
+
+#[allow(dead_code)]
+pub fn reboot() -> ! {
+    x86_64::instructions::interrupts::disable();
+    let err = crate::acpi::fadt::reset();
+    crate::kprintln!("acpi reset didn't work: {}", err);
+    crate::uefi_runtime::reset_system(crate::uefi_runtime::ResetType::Cold);
+    abort()
+}
+
+//the kernel's own tables once kinit has switched to them
+static PAGE_TABLE: spin::Once<spin::Mutex<mmu::Pml4>> = spin::Once::new();
+
+//device registers get identity mapped uncached into the otherwise empty lower half, the physical
+//memory map is write-back and only reaches as far as the firmware's memory map. returns where they are
+pub fn map_mmio(phys_start: u64, len: u64) -> u64 {
+    let mut allocator = ALLOCATOR.lock();
+    let mut page_table = PAGE_TABLE
+        .get()
+        .expect("kernel page tables not set up yet")
+        .lock();
+    unsafe {
+        crate::mmu::identity_map_region(
+            &mut allocator,
+            &mut *page_table,
+            phys_start as usize,
+            (phys_start + len) as usize,
+            mmu::PageFlags::MMIO,
+        )
+        .unwrap();
+    }
+    let mapped = page_table.find_map(crate::mmu::VirtualAddr(phys_start as usize));
+    assert!(mapped.is_ok_and(|phys| phys.0 == phys_start as usize));
+    phys_start
+}
+
+static BOOT_INFO: spin::Once<&'static boot_info::BootInfo> = spin::Once::new();
+
+pub fn boot_info() -> &'static boot_info::BootInfo {
+    BOOT_INFO.get().expect("boot info not set yet")
+}
+
 #[no_mangle]
-pub fn kinit() {
-    todo!()
+pub extern "C" fn kinit(boot_info: &'static boot_info::BootInfo) -> ! {
+    //nothing to print with yet, so a bad handoff just stops here
+    if boot_info.check().is_err() {
+        abort();
+    }
+    BOOT_INFO.call_once(|| boot_info);
+    crate::symbolize::init(boot_info);
+
+    gdt::init();
+    idt::init();
+
+    //off the bootloader's page tables and onto ones the kernel owns, with everything it mapped
+    {
+        let mut allocator = ALLOCATOR.lock();
+        let mut page_table = mmu::Pml4::new(&mut allocator).unwrap();
+        unsafe {
+            page_table
+                .copy_kernel_half(&mut allocator, mmu::Pml4::active_root())
+                .unwrap();
+            page_table.activate().unwrap();
+        }
+        PAGE_TABLE.call_once(|| spin::Mutex::new(page_table));
+    }
+
+    if let Some(runtime_services_addr) = boot_info.runtime_services_addr() {
+        unsafe { crate::uefi_runtime::init(runtime_services_addr) };
+    }
+
+    if let Some(framebuffer) = boot_info.framebuffer() {
+        let console = unsafe {
+            crate::framebuffer::FrameBufferConsole::new(
+                framebuffer,
+                boot_info.physical_memory_offset,
+            )
+        };
+        *crate::framebuffer::CONSOLE.lock() = Some(console);
+    }
+    crate::kprintln!("早上好");
+    match crate::uefi_runtime::get_time() {
+        Ok(time) => crate::kprintln!(
+            "it's {}-{:02}-{:02} {:02}:{:02}:{:02}",
+            time.year,
+            time.month,
+            time.day,
+            time.hour,
+            time.minute,
+            time.second
+        ),
+        Err(err) => crate::kprintln!("no wall clock: {}", err),
+    }
+    if let Ok(entry) = crate::uefi_runtime::boot_current() {
+        crate::kprintln!("started from Boot{:04X}", entry);
+    }
+
+    if let Some(rsdp_addr) = boot_info.rsdp_addr() {
+        if let Err(err) = crate::acpi::init(rsdp_addr, boot_info.physical_memory_offset) {
+            crate::kprintln!("no acpi: {}", err);
+        }
+    }
+    if let Some(hpet) = crate::acpi::hpet::Hpet::get() {
+        crate::kprintln!(
+            "hpet {} at {:#x}, {} comparators, {} bit counter",
+            hpet.number,
+            hpet.base_address.address,
+            hpet.num_comparators,
+            if hpet.counter_64_bit { 64 } else { 32 }
+        );
+    }
+    for entry in crate::acpi::mcfg::Mcfg::get()
+        .iter()
+        .flat_map(|mcfg| mcfg.entries())
+    {
+        crate::kprintln!(
+            "pcie segment {} buses {}-{} at {:#x}",
+            entry.segment,
+            entry.start_bus,
+            entry.end_bus,
+            entry.base_address
+        );
+    }
+    apic::init();
+    interrupt::set_threshold(0);
+    if apic::has_route(COM1_IRQ) {
+        interrupt::enable(COM1_IRQ);
+        interrupt::set_priority(COM1_IRQ, 1);
+        //the trap handler queues what comes in for kmain
+        WRITER.lock().enable_receive_interrupt();
+    } else {
+        crate::kprintln!("no io apic has com1's irq, not taking serial input");
+    }
+    x86_64::instructions::interrupts::enable();
+
+    switch_to_kernel_stack(crate::kmain)
+}
+
+//the bootloader's stack and the frames on it are never returned to
+fn switch_to_kernel_stack(entry: extern "C" fn() -> !) -> ! {
+    unsafe {
+        core::arch::asm!(
+            r#"
+            mov rsp, {}
+            xor rbp, rbp
+            call {}
+            ud2
+            "#,
+            in(reg) gdt::kernel_stack_top().as_u64(),
+            in(reg) entry,
+            options(noreturn),
+        );
+    }
 }
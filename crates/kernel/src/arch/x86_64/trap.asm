@@ -0,0 +1,70 @@
+	/* one 16 byte stub per vector, so the idt can point at trap_stubs + 16 * vector.
+	   the stubs make every trap look the same: an error code (0 if the cpu doesn't push one)
+	   and the vector on top of what the cpu pushed */
+	.altmacro
+	.macro trap_stub vector
+	.balign 16
+	.if !(\vector == 8 || \vector == 10 || \vector == 11 || \vector == 12 || \vector == 13 || \vector == 14 || \vector == 17 || \vector == 21 || \vector == 29 || \vector == 30)
+	push 0
+	.endif
+	push \vector
+	jmp trap_common
+	.endm
+
+	.section .text
+	.balign 16
+	.global trap_stubs
+trap_stubs:
+	.set i, 0
+	.rept 256
+	trap_stub %i
+	.set i, i+1
+	.endr
+
+	/* saves everything into a TrapFrame, see trap.rs for the layout */
+trap_common:
+	push rax
+	push rbx
+	push rcx
+	push rdx
+	push rsi
+	push rdi
+	push rbp
+	push r8
+	push r9
+	push r10
+	push r11
+	push r12
+	push r13
+	push r14
+	push r15
+
+	/* a fake frame for the interrupted rip, so backtraces go on into whatever got interrupted.
+	   the cpu aligned the stack to 16 and this keeps it that way for the call */
+	push qword ptr [rsp + 136]
+	push rbp
+	mov rbp, rsp
+	lea rdi, [rsp + 16]
+	cld
+	call rust_andy_trap
+	add rsp, 16
+
+	pop r15
+	pop r14
+	pop r13
+	pop r12
+	pop r11
+	pop r10
+	pop r9
+	pop r8
+	pop rbp
+	pop rdi
+	pop rsi
+	pop rdx
+	pop rcx
+	pop rbx
+	pop rax
+
+	/* vector and error code */
+	add rsp, 16
+	iretq
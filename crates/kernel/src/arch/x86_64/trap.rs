@@ -0,0 +1,175 @@
+core::arch::global_asm!(include_str!("trap.asm"));
+
+use crate::kprintln;
+use core::fmt;
+
+pub const BREAKPOINT: u8 = 3;
+pub const NMI: u8 = 2;
+pub const PAGE_FAULT: u8 = 14;
+
+const EXCEPTION_NAMES: [&str; 32] = [
+    "divide error",
+    "debug",
+    "non-maskable interrupt",
+    "breakpoint",
+    "overflow",
+    "bound range exceeded",
+    "invalid opcode",
+    "device not available",
+    "double fault",
+    "coprocessor segment overrun",
+    "invalid tss",
+    "segment not present",
+    "stack-segment fault",
+    "general protection fault",
+    "page fault",
+    "reserved",
+    "x87 floating point exception",
+    "alignment check",
+    "machine check",
+    "simd floating point exception",
+    "virtualization exception",
+    "control protection exception",
+    "reserved",
+    "reserved",
+    "reserved",
+    "reserved",
+    "reserved",
+    "reserved",
+    "hypervisor injection exception",
+    "vmm communication exception",
+    "security exception",
+    "reserved",
+];
+
+//what trap_common leaves on the stack, lowest address first
+#[repr(C)]
+#[derive(Debug)]
+pub struct TrapFrame {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    pub vector: u64,
+    //0 for vectors the cpu doesn't push one for
+    pub error_code: u64,
+    //pushed by the cpu
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+impl fmt::Display for TrapFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "rax={:016x} rbx={:016x} rcx={:016x} rdx={:016x}",
+            self.rax, self.rbx, self.rcx, self.rdx
+        )?;
+        writeln!(
+            f,
+            "rsi={:016x} rdi={:016x} rbp={:016x} rsp={:016x}",
+            self.rsi, self.rdi, self.rbp, self.rsp
+        )?;
+        writeln!(
+            f,
+            "r8 ={:016x} r9 ={:016x} r10={:016x} r11={:016x}",
+            self.r8, self.r9, self.r10, self.r11
+        )?;
+        writeln!(
+            f,
+            "r12={:016x} r13={:016x} r14={:016x} r15={:016x}",
+            self.r12, self.r13, self.r14, self.r15
+        )?;
+        write!(
+            f,
+            "rip={:016x} cs={:04x} rflags={:016x} ss={:04x}",
+            self.rip, self.cs, self.rflags, self.ss
+        )
+    }
+}
+
+pub fn exception_name(vector: u8) -> Option<&'static str> {
+    EXCEPTION_NAMES.get(vector as usize).copied()
+}
+
+//the idt points every vector at trap_stubs + TRAP_STUB_SIZE * vector
+pub const TRAP_STUB_SIZE: u64 = 16;
+
+pub fn trap_stub_addr(vector: u8) -> u64 {
+    extern "C" {
+        static trap_stubs: u8;
+    }
+    core::ptr::addr_of!(trap_stubs) as u64 + TRAP_STUB_SIZE * vector as u64
+}
+
+#[no_mangle]
+extern "C" fn rust_andy_trap(frame: &mut TrapFrame) {
+    let vector = frame.vector as u8;
+    match vector {
+        //int3 leaves rip after itself, so there's nothing to fix up to carry on
+        BREAKPOINT => {
+            kprintln!("breakpoint at {:#x}", frame.rip);
+            kprintln!("{}", frame);
+        }
+        //nothing sends these on purpose yet, so just say something and go on
+        NMI => {
+            kprintln!("non-maskable interrupt at {:#x}", frame.rip);
+        }
+        0..=31 => exception(vector, frame),
+        _ => {
+            if !super::idt::dispatch(vector, frame) {
+                panic!("unhandled interrupt: {}", vector);
+            }
+        }
+    }
+}
+
+fn exception(vector: u8, frame: &TrapFrame) -> ! {
+    let name = exception_name(vector).unwrap_or("reserved");
+    kprintln!(
+        "exception {} ({}) error code {:#x}",
+        vector,
+        name,
+        frame.error_code
+    );
+    if vector == PAGE_FAULT {
+        let cr2 = x86_64::registers::control::Cr2::read_raw();
+        kprintln!("cr2={:016x}", cr2);
+    }
+    kprintln!("{}", frame);
+    panic!("unhandled exception: {}", name);
+}
+
+//everything interrupt::enable routes ends up here
+pub fn external_interrupt_handler(_vector: u8, _frame: &mut TrapFrame) {
+    if let Some(interrupt) = super::interrupt::next_interrupt() {
+        match interrupt {
+            super::COM1_IRQ => {
+                //straight from the port, what this interrupted might be holding the writer's lock.
+                //all of it too, the irq is edge triggered and doesn't come again for what's left
+                let mut port = unsafe { crate::uart::PortSerial::new(super::COM1) };
+                while let Some(byte) = crate::uart::read_byte_from(&mut port) {
+                    super::SERIAL_INPUT.push(byte);
+                }
+            }
+            _ => panic!("unhandled interrupt: {}", interrupt),
+        }
+        super::interrupt::complete(interrupt);
+    } else {
+        panic!("external interrupt with nothing in service");
+    }
+}
@@ -0,0 +1,171 @@
+use boot_info::{FrameBufferInfo, PixelFormat};
+
+//unifont glyphs are 16 pixels high and either 8 or 16 wide, fullwidth ones take two cells
+const GLYPH_HEIGHT: usize = 16;
+const CELL_WIDTH: usize = 8;
+const TAB_WIDTH: usize = 4;
+
+//None until the arch code finds a framebuffer in the boot info
+pub static CONSOLE: spin::Mutex<Option<FrameBufferConsole>> = spin::Mutex::new(None);
+
+pub struct FrameBufferConsole {
+    buffer: *mut u32,
+    stride: usize,
+    columns: usize,
+    rows: usize,
+    column: usize,
+    row: usize,
+    foreground: u32,
+    background: u32,
+}
+
+//the framebuffer is only ever touched through the CONSOLE lock
+unsafe impl Send for FrameBufferConsole {}
+
+impl FrameBufferConsole {
+    /// # Safety
+    /// the framebuffer has to be mapped at physical_memory_offset + info.addr and not used by anything else
+    pub unsafe fn new(info: &FrameBufferInfo, physical_memory_offset: u64) -> Self {
+        let mut console = FrameBufferConsole {
+            buffer: (physical_memory_offset + info.addr) as *mut u32,
+            stride: info.stride as usize,
+            columns: info.width as usize / CELL_WIDTH,
+            rows: info.height as usize / GLYPH_HEIGHT,
+            column: 0,
+            row: 0,
+            foreground: encode_color(info.pixel_format, 0xaa, 0xaa, 0xaa),
+            background: encode_color(info.pixel_format, 0, 0, 0),
+        };
+        console.clear();
+        console
+    }
+
+    pub fn clear(&mut self) {
+        for row in 0..self.rows {
+            self.clear_row(row);
+        }
+        self.column = 0;
+        self.row = 0;
+    }
+
+    pub fn write_char(&mut self, c: char) {
+        match c {
+            '\n' => self.newline(),
+            '\r' => self.column = 0,
+            '\t' => {
+                let next_stop = (self.column / TAB_WIDTH + 1) * TAB_WIDTH;
+                while self.column < next_stop && self.column < self.columns {
+                    self.draw_blank(self.column, self.row);
+                    self.column += 1;
+                }
+            }
+            '\x08' => self.column = self.column.saturating_sub(1),
+            c if c.is_control() => {}
+            c => {
+                let glyph = unifont::get_glyph(c)
+                    .or_else(|| unifont::get_glyph(char::REPLACEMENT_CHARACTER))
+                    .expect("font has no replacement character");
+                let cells = glyph.get_width() / CELL_WIDTH;
+                if self.column + cells > self.columns {
+                    self.newline();
+                }
+                self.draw_glyph(glyph, self.column, self.row);
+                self.column += cells;
+            }
+        }
+    }
+
+    fn newline(&mut self) {
+        self.column = 0;
+        if self.row + 1 < self.rows {
+            self.row += 1;
+        } else {
+            self.scroll();
+        }
+    }
+
+    //moves every text row up by one and blanks the last
+    fn scroll(&mut self) {
+        if self.rows == 0 {
+            return;
+        }
+        let row_pixels = GLYPH_HEIGHT * self.stride;
+        unsafe {
+            core::ptr::copy(
+                self.buffer.add(row_pixels),
+                self.buffer,
+                (self.rows - 1) * row_pixels,
+            );
+        }
+        self.clear_row(self.rows - 1);
+    }
+
+    fn clear_row(&mut self, row: usize) {
+        for column in 0..self.columns {
+            self.draw_blank(column, row);
+        }
+    }
+
+    fn draw_blank(&mut self, column: usize, row: usize) {
+        for y in 0..GLYPH_HEIGHT {
+            for x in 0..CELL_WIDTH {
+                self.put_pixel(
+                    column * CELL_WIDTH + x,
+                    row * GLYPH_HEIGHT + y,
+                    self.background,
+                );
+            }
+        }
+    }
+
+    fn draw_glyph(&mut self, glyph: &unifont::Glyph, column: usize, row: usize) {
+        for y in 0..GLYPH_HEIGHT {
+            for x in 0..glyph.get_width() {
+                let color = if glyph.get_pixel(x, y) {
+                    self.foreground
+                } else {
+                    self.background
+                };
+                self.put_pixel(column * CELL_WIDTH + x, row * GLYPH_HEIGHT + y, color);
+            }
+        }
+    }
+
+    fn put_pixel(&mut self, x: usize, y: usize, color: u32) {
+        unsafe { self.buffer.add(y * self.stride + x).write_volatile(color) };
+    }
+}
+
+impl core::fmt::Write for FrameBufferConsole {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for c in s.chars() {
+            self.write_char(c);
+        }
+        Ok(())
+    }
+}
+
+fn encode_color(format: PixelFormat, red: u8, green: u8, blue: u8) -> u32 {
+    match format {
+        PixelFormat::Rgb => red as u32 | (green as u32) << 8 | (blue as u32) << 16,
+        PixelFormat::Bgr => blue as u32 | (green as u32) << 8 | (red as u32) << 16,
+        PixelFormat::Bitmask {
+            red: red_mask,
+            green: green_mask,
+            blue: blue_mask,
+        } => {
+            encode_channel(red, red_mask)
+                | encode_channel(green, green_mask)
+                | encode_channel(blue, blue_mask)
+        }
+    }
+}
+
+//keeps the top bits of the value that fit in the mask
+fn encode_channel(value: u8, mask: u32) -> u32 {
+    if mask == 0 {
+        return 0;
+    }
+    let bits = mask.count_ones().min(8);
+    ((value as u32 >> (8 - bits)) << mask.trailing_zeros()) & mask
+}
@@ -133,3 +133,15 @@ impl<const PAGE_SIZE: usize> AndyAllocator<PAGE_SIZE> {
         page_addr
     }
 }
+
+//unifont links the alloc crate, which needs a global allocator even though nothing allocates through
+//it. anything that does gets null back and ends up in the alloc error panic instead of leaking pages
+pub struct NoAllocator;
+
+unsafe impl core::alloc::GlobalAlloc for NoAllocator {
+    unsafe fn alloc(&self, _layout: core::alloc::Layout) -> *mut u8 {
+        core::ptr::null_mut()
+    }
+
+    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: core::alloc::Layout) {}
+}
@@ -1,14 +1,34 @@
 #![no_std]
 #![no_main]
 
+#[cfg(target_arch = "x86_64")]
+mod acpi;
 mod arch;
+#[cfg(target_arch = "x86_64")]
+mod framebuffer;
 mod heap_alloc;
+mod mmu;
+mod symbolize;
 mod uart;
+#[cfg(target_arch = "x86_64")]
+mod uefi_runtime;
 
 use arch::special::WRITER;
 
+//unifont pulls in the alloc crate
+#[global_allocator]
+static GLOBAL_ALLOCATOR: heap_alloc::NoAllocator = heap_alloc::NoAllocator;
+
 pub fn print(args: core::fmt::Arguments) {
     use core::fmt::Write;
+    //only x86 gets a framebuffer handed over. a panic or interrupt that prints while the console is
+    //drawing skips it instead of spinning on its own lock, the serial port still gets everything
+    #[cfg(target_arch = "x86_64")]
+    if let Some(mut console) = framebuffer::CONSOLE.try_lock() {
+        if let Some(console) = console.as_mut() {
+            let _ = console.write_fmt(args);
+        }
+    }
     WRITER.lock().write_fmt(args).unwrap();
 }
 
@@ -26,10 +46,16 @@ macro_rules! kprintln {
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
     kprintln!("PANIC: {:?}", info);
+    symbolize::print_backtrace();
     arch::special::abort()
 }
 
 #[no_mangle]
-fn kmain() -> ! {
-    loop {}
+extern "C" fn kmain() -> ! {
+    loop {
+        match arch::special::SERIAL_INPUT.pop() {
+            Some(byte) => kprintln!("got byte {}", byte),
+            None => arch::special::wait_for_input(),
+        }
+    }
 }
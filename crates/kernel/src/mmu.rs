@@ -0,0 +1,42 @@
+//what every architecture's page tables look like to the rest of the kernel
+#[derive(Debug, Copy, Clone)]
+pub struct VirtualAddr(pub usize);
+#[derive(Debug, Copy, Clone)]
+pub struct PhysicalAddr(pub usize);
+
+pub trait VirtualMemoryScheme {
+    type MapError: core::fmt::Debug;
+    type MapProtection: Copy;
+
+    fn new(allocator: &mut crate::heap_alloc::AndyAllocator<4096>) -> Result<Self, Self::MapError>
+    where
+        Self: Sized;
+
+    unsafe fn create_mapping(
+        &mut self,
+        allocator: &mut crate::heap_alloc::AndyAllocator<4096>,
+        virtual_page_num: usize,
+        physical_page_num: usize,
+        protiection: Self::MapProtection,
+    ) -> Result<(), Self::MapError>;
+
+    fn find_map(&self, from: VirtualAddr) -> Result<PhysicalAddr, Self::MapError>;
+
+    unsafe fn activate(&self) -> Result<(), Self::MapError>;
+}
+
+pub unsafe fn identity_map_region<T: VirtualMemoryScheme>(
+    allocator: &mut crate::heap_alloc::AndyAllocator<4096>,
+    table: &mut T,
+    start_addr: usize,
+    end_addr: usize,
+    protection: T::MapProtection,
+) -> Result<(), T::MapError> {
+    let start_page = start_addr / 4096;
+    let end_page = end_addr.div_ceil(4096);
+    for page in start_page..end_page {
+        unsafe { table.create_mapping(allocator, page, page, protection)? };
+    }
+
+    Ok(())
+}
@@ -0,0 +1,167 @@
+//turns kernel addresses back into function+offset, with the symbol table the bootloader hands over
+use crate::kprintln;
+use core::fmt;
+use core::fmt::Write;
+
+//a corrupt chain could go on forever
+const MAX_FRAMES: usize = 64;
+
+static BOOT_INFO: spin::Once<&'static boot_info::BootInfo> = spin::Once::new();
+
+//the riscv64 kernel doesn't get a boot info yet, so it never has symbols
+#[cfg(target_arch = "x86_64")]
+pub fn init(boot_info: &'static boot_info::BootInfo) {
+    BOOT_INFO.call_once(|| boot_info);
+}
+
+pub struct Location {
+    pub name: &'static str,
+    pub offset: u64,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}+{:#x}", Demangle(self.name), self.offset)
+    }
+}
+
+//the closest function at or below addr. asm functions without a .size only get the first part
+pub fn symbolize(addr: u64) -> Option<Location> {
+    let boot_info = BOOT_INFO.get()?;
+    let slide = boot_info.kernel_slide;
+
+    let symbol = boot_info
+        .symbols()
+        .iter()
+        .filter(|symbol| symbol.is_function() && symbol.value != 0)
+        .filter(|symbol| symbol.value.wrapping_add(slide) <= addr)
+        .max_by_key(|symbol| symbol.value)?;
+    let offset = addr - symbol.value.wrapping_add(slide);
+    if symbol.size != 0 && offset >= symbol.size {
+        return None;
+    }
+
+    Some(Location {
+        name: boot_info.symbol_name(symbol)?,
+        offset,
+    })
+}
+
+//walks the frame pointer chain, the kernel is built with frame pointers for this
+pub fn print_backtrace() {
+    kprintln!("backtrace:");
+    let mut frame_pointer = crate::arch::special::frame_pointer();
+    for _ in 0..MAX_FRAMES {
+        //the bootloader starts the kernel with a null frame pointer
+        if frame_pointer == 0 || !frame_pointer.is_multiple_of(8) {
+            break;
+        }
+        let (caller_frame_pointer, return_addr) =
+            unsafe { crate::arch::special::unwind_frame(frame_pointer) };
+        if return_addr == 0 {
+            break;
+        }
+        //the return address is already past the call, which might be the last instruction
+        match symbolize(return_addr as u64 - 1) {
+            Some(location) => kprintln!("  {:#018x} {}", return_addr, location),
+            None => kprintln!("  {:#018x}", return_addr),
+        }
+
+        //callers' frames are further up the stack
+        if caller_frame_pointer <= frame_pointer {
+            break;
+        }
+        frame_pointer = caller_frame_pointer;
+    }
+}
+
+//legacy rust mangling, _ZN then length prefixed parts then E, the last part being a hash.
+//v0 names (_R) and anything else get printed as they are
+struct Demangle<'a>(&'a str);
+
+impl fmt::Display for Demangle<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Some(parts) = self
+            .0
+            .strip_prefix("_ZN")
+            .and_then(|name| name.strip_suffix('E'))
+        else {
+            return f.write_str(self.0);
+        };
+
+        //check the whole thing first so a bad name doesn't come out half demangled
+        let mut rest = parts;
+        while !rest.is_empty() {
+            if next_part(&mut rest).is_none() {
+                return f.write_str(self.0);
+            }
+        }
+
+        let mut rest = parts;
+        let mut first = true;
+        while let Some(part) = next_part(&mut rest) {
+            if rest.is_empty() && is_hash(part) {
+                break;
+            }
+            if !first {
+                f.write_str("::")?;
+            }
+            first = false;
+            write_part(f, part)?;
+        }
+        Ok(())
+    }
+}
+
+fn next_part<'a>(rest: &mut &'a str) -> Option<&'a str> {
+    let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+    let len: usize = rest[..digits].parse().ok()?;
+    let part = rest.get(digits..digits + len)?;
+    *rest = &rest[digits + len..];
+    Some(part)
+}
+
+fn is_hash(part: &str) -> bool {
+    part.len() == 17 && part.starts_with('h') && part[1..].bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+fn write_part(f: &mut fmt::Formatter, part: &str) -> fmt::Result {
+    //parts can't start with $, so it gets an _ in front
+    let mut rest = part.strip_prefix("_$").map_or(part, |_| &part[1..]);
+    while let Some(c) = rest.chars().next() {
+        if let Some(after) = rest.strip_prefix("..") {
+            f.write_str("::")?;
+            rest = after;
+            continue;
+        }
+        if c == '$' {
+            if let Some(end) = rest[1..].find('$') {
+                if let Some(unescaped) = unescape(&rest[1..end + 1]) {
+                    f.write_char(unescaped)?;
+                    rest = &rest[end + 2..];
+                    continue;
+                }
+            }
+        }
+        f.write_char(c)?;
+        rest = &rest[c.len_utf8()..];
+    }
+    Ok(())
+}
+
+fn unescape(escape: &str) -> Option<char> {
+    match escape {
+        "SP" => Some('@'),
+        "BP" => Some('*'),
+        "RF" => Some('&'),
+        "LT" => Some('<'),
+        "GT" => Some('>'),
+        "LP" => Some('('),
+        "RP" => Some(')'),
+        "C" => Some(','),
+        _ => escape
+            .strip_prefix('u')
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .and_then(char::from_u32),
+    }
+}
@@ -1,40 +1,209 @@
-pub struct UartWriter {
-    uart_addr: usize,
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+//16550 registers, the same whether they're reached through mmio or port i/o
+const DATA: u16 = 0; //divisor latch low while DLAB is set
+const INTERRUPT_ENABLE: u16 = 1; //divisor latch high while DLAB is set
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+
+const LINE_CONTROL_8N1: u8 = 0b11;
+const LINE_CONTROL_DLAB: u8 = 1 << 7;
+const LINE_STATUS_DATA_READY: u8 = 1 << 0;
+const LINE_STATUS_THR_EMPTY: u8 = 1 << 5;
+
+//what the divisor divides, the usual 1.8432MHz crystal over 16
+const BASE_BAUD: u32 = 115200;
+
+//a power of two, so the indices can wrap around
+const RECEIVE_BUFFER_SIZE: usize = 256;
+
+//how a 16550's registers are reached
+pub trait SerialPort {
+    /// # Safety
+    /// reg has to be one of the 16550's registers, reading some of them has side effects
+    unsafe fn read_reg(&mut self, reg: u16) -> u8;
+    /// # Safety
+    /// reg has to be one of the 16550's registers
+    unsafe fn write_reg(&mut self, reg: u16, val: u8);
+}
+
+impl<P: SerialPort> SerialPort for &mut P {
+    unsafe fn read_reg(&mut self, reg: u16) -> u8 {
+        unsafe { (**self).read_reg(reg) }
+    }
+
+    unsafe fn write_reg(&mut self, reg: u16, val: u8) {
+        unsafe { (**self).write_reg(reg, val) }
+    }
+}
+
+//riscv's virt machine, registers are one byte apart
+#[cfg(target_arch = "riscv64")]
+pub struct MmioSerial {
+    base: usize,
+}
+
+#[cfg(target_arch = "riscv64")]
+impl MmioSerial {
+    /// # Safety
+    /// base has to be a 16550 that nothing else uses
+    pub unsafe fn new(base: usize) -> Self {
+        MmioSerial { base }
+    }
+}
+
+#[cfg(target_arch = "riscv64")]
+impl SerialPort for MmioSerial {
+    unsafe fn read_reg(&mut self, reg: u16) -> u8 {
+        unsafe { (self.base as *const u8).add(reg as usize).read_volatile() }
+    }
+
+    unsafe fn write_reg(&mut self, reg: u16, val: u8) {
+        unsafe { (self.base as *mut u8).add(reg as usize).write_volatile(val) }
+    }
+}
+
+//the pc's COM ports
+#[cfg(target_arch = "x86_64")]
+pub struct PortSerial {
+    base: u16,
+}
+
+#[cfg(target_arch = "x86_64")]
+impl PortSerial {
+    /// # Safety
+    /// base has to be a 16550 that nothing else uses
+    pub unsafe fn new(base: u16) -> Self {
+        PortSerial { base }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl SerialPort for PortSerial {
+    unsafe fn read_reg(&mut self, reg: u16) -> u8 {
+        let mut port = x86_64::instructions::port::Port::new(self.base + reg);
+        unsafe { port.read() }
+    }
+
+    unsafe fn write_reg(&mut self, reg: u16, val: u8) {
+        let mut port = x86_64::instructions::port::Port::new(self.base + reg);
+        unsafe { port.write(val) }
+    }
 }
 
-impl UartWriter {
-    pub unsafe fn new(uart_addr: usize) -> Self {
-        let uart_ptr: *mut u8 = uart_addr as *mut u8;
+pub struct UartWriter<P: SerialPort> {
+    port: P,
+}
 
+impl<P: SerialPort> UartWriter<P> {
+    //8n1 with the fifo on and interrupts off
+    pub fn new(mut port: P, baud: u32) -> Self {
+        let divisor = (BASE_BAUD / baud.clamp(1, BASE_BAUD)) as u16;
         unsafe {
-            uart_ptr.add(3).write_volatile(0b11); //set word length to 8
-            uart_ptr.add(2).write_volatile(0b1); //enable fifo
-            uart_ptr.add(1).write_volatile(0b1); //enable recieving buffer interrupts
+            port.write_reg(INTERRUPT_ENABLE, 0);
+            port.write_reg(LINE_CONTROL, LINE_CONTROL_DLAB);
+            port.write_reg(DATA, divisor as u8);
+            port.write_reg(INTERRUPT_ENABLE, (divisor >> 8) as u8);
+            port.write_reg(LINE_CONTROL, LINE_CONTROL_8N1);
+            port.write_reg(FIFO_CONTROL, 0b1100_0111); //enable and clear, interrupt at 14 bytes
+            port.write_reg(MODEM_CONTROL, 0b1011); //dtr, rts, and out2 which gates the irq on pcs
         }
 
-        UartWriter { uart_addr }
+        UartWriter { port }
+    }
+
+    pub fn enable_receive_interrupt(&mut self) {
+        unsafe { self.port.write_reg(INTERRUPT_ENABLE, 0b1) };
     }
 
+    //waits for room in the transmit holding register first
     pub fn write_byte(&mut self, byte: u8) {
-        let uart_ptr: *mut u8 = self.uart_addr as *mut u8;
-        unsafe {
-            uart_ptr.add(0).write_volatile(byte);
+        while unsafe { self.port.read_reg(LINE_STATUS) } & LINE_STATUS_THR_EMPTY == 0 {
+            core::hint::spin_loop();
         }
+        unsafe { self.port.write_reg(DATA, byte) };
+    }
+
+    fn data_ready(&mut self) -> bool {
+        let line_status = unsafe { self.port.read_reg(LINE_STATUS) };
+        line_status & LINE_STATUS_DATA_READY != 0
     }
 
     pub fn read_byte(&mut self) -> Option<u8> {
-        let uart_ptr: *mut u8 = self.uart_addr as *mut u8;
-        let has_data: bool = unsafe { uart_ptr.add(5).read_volatile() & 1 == 1 };
-        if has_data {
-            let data: u8 = unsafe { uart_ptr.add(0).read_volatile() };
-            Some(data)
+        if self.data_ready() {
+            Some(unsafe { self.port.read_reg(DATA) })
         } else {
             None
         }
     }
+
+    #[allow(dead_code)] //input comes in through the receive interrupt for now
+    pub fn read_byte_blocking(&mut self) -> u8 {
+        while !self.data_ready() {
+            core::hint::spin_loop();
+        }
+        unsafe { self.port.read_reg(DATA) }
+    }
+}
+
+//for the receive interrupt, which can't wait on whoever holds the writer. borrows a port that new
+//already set up instead of setting it up again
+pub fn read_byte_from<P: SerialPort>(port: &mut P) -> Option<u8> {
+    UartWriter { port }.read_byte()
+}
+
+//what the receive interrupt drained, until kmain gets to it. the interrupt handler is the only one
+//pushing and kmain the only one popping, so the two indices are all the locking it needs
+pub struct ReceiveBuffer {
+    bytes: [AtomicU8; RECEIVE_BUFFER_SIZE],
+    //only ever count up
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+impl ReceiveBuffer {
+    pub const fn new() -> Self {
+        ReceiveBuffer {
+            bytes: [const { AtomicU8::new(0) }; RECEIVE_BUFFER_SIZE],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    //a full buffer drops the byte
+    pub fn push(&self, byte: u8) {
+        let tail = self.tail.load(Ordering::Relaxed);
+        if tail.wrapping_sub(self.head.load(Ordering::Acquire)) == RECEIVE_BUFFER_SIZE {
+            return;
+        }
+        self.bytes[tail % RECEIVE_BUFFER_SIZE].store(byte, Ordering::Relaxed);
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+    }
+
+    pub fn pop(&self) -> Option<u8> {
+        let head = self.head.load(Ordering::Relaxed);
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        let byte = self.bytes[head % RECEIVE_BUFFER_SIZE].load(Ordering::Relaxed);
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(byte)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Acquire) == self.tail.load(Ordering::Acquire)
+    }
+}
+
+impl Default for ReceiveBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl core::fmt::Write for UartWriter {
+impl<P: SerialPort> core::fmt::Write for UartWriter<P> {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
         for c in s.as_bytes() {
             self.write_byte(*c);
@@ -0,0 +1,218 @@
+//uefi runtime services, the bootloader already called SetVirtualAddressMap so the table and
+//everything it points at are mapped in the kernel's page table. the firmware isn't reentrant,
+//so every call goes through the lock
+
+//the top bit of a status means error
+const STATUS_ERROR_BIT: usize = 1 << (usize::BITS - 1);
+const STATUS_BUFFER_TOO_SMALL: usize = STATUS_ERROR_BIT | 5;
+
+static RUNTIME_SERVICES: spin::Mutex<Option<&'static RuntimeServices>> = spin::Mutex::new(None);
+
+#[repr(C)]
+struct TableHeader {
+    signature: u64,
+    revision: u32,
+    size: u32,
+    crc32: u32,
+    reserved: u32,
+}
+
+//only the functions the kernel calls have real types, the rest just keep the layout
+#[repr(C)]
+struct RuntimeServices {
+    header: TableHeader,
+    get_time: unsafe extern "efiapi" fn(time: *mut Time, capabilities: *mut u8) -> usize,
+    set_time: usize,
+    get_wakeup_time: usize,
+    set_wakeup_time: usize,
+    set_virtual_address_map: usize,
+    convert_pointer: usize,
+    get_variable: unsafe extern "efiapi" fn(
+        name: *const u16,
+        vendor: *const Guid,
+        attributes: *mut u32,
+        data_size: *mut usize,
+        data: *mut u8,
+    ) -> usize,
+    get_next_variable_name: usize,
+    set_variable: unsafe extern "efiapi" fn(
+        name: *const u16,
+        vendor: *const Guid,
+        attributes: u32,
+        data_size: usize,
+        data: *const u8,
+    ) -> usize,
+    get_next_high_monotonic_count: usize,
+    reset_system: unsafe extern "efiapi" fn(
+        reset_type: ResetType,
+        status: usize,
+        data_size: usize,
+        data: *const u8,
+    ) -> !,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Time {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pad1: u8,
+    pub nanosecond: u32,
+    //minutes from utc, 2047 means local time
+    pub time_zone: i16,
+    pub daylight: u8,
+    pad2: u8,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Guid {
+    pub data1: u32,
+    pub data2: u16,
+    pub data3: u16,
+    pub data4: [u8; 8],
+}
+
+//vendor of the variables from the uefi spec, like BootOrder and Timeout
+pub const GLOBAL_VARIABLE: Guid = Guid {
+    data1: 0x8be4df61,
+    data2: 0x93ca,
+    data3: 0x11d2,
+    data4: [0xaa, 0x0d, 0x00, 0xe0, 0x98, 0x03, 0x2b, 0x8c],
+};
+
+#[allow(dead_code)] //for set_variable, nothing in the kernel writes variables yet
+pub const VARIABLE_NON_VOLATILE: u32 = 0x1;
+#[allow(dead_code)]
+pub const VARIABLE_BOOTSERVICE_ACCESS: u32 = 0x2;
+#[allow(dead_code)]
+pub const VARIABLE_RUNTIME_ACCESS: u32 = 0x4;
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResetType {
+    Cold,
+    #[allow(dead_code)] //reboot goes cold
+    Warm,
+    Shutdown,
+}
+
+#[derive(Debug)]
+pub enum RuntimeError {
+    //the bootloader couldn't keep runtime services around
+    Unavailable,
+    //the raw efi status
+    Status(usize),
+    BufferTooSmall { needed: usize },
+}
+
+impl core::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            RuntimeError::Unavailable => write!(f, "no runtime services"),
+            RuntimeError::Status(status) => write!(f, "efi status {:#x}", status),
+            RuntimeError::BufferTooSmall { needed } => {
+                write!(f, "buffer too small, needs {} bytes", needed)
+            }
+        }
+    }
+}
+
+/// # Safety
+/// addr has to be the runtime services address from the boot info
+pub unsafe fn init(addr: u64) {
+    let runtime_services = unsafe { &*(addr as *const RuntimeServices) };
+    *RUNTIME_SERVICES.lock() = Some(runtime_services);
+}
+
+pub fn get_time() -> Result<Time, RuntimeError> {
+    let runtime_services = RUNTIME_SERVICES.lock();
+    let runtime_services = runtime_services.ok_or(RuntimeError::Unavailable)?;
+
+    let mut time = Time::default();
+    let status = unsafe { (runtime_services.get_time)(&mut time, core::ptr::null_mut()) };
+    check_status(status)?;
+    Ok(time)
+}
+
+//name is utf-16 and has to end in a nul, returns the attributes and how much of data got filled
+pub fn get_variable(
+    name: &[u16],
+    vendor: &Guid,
+    data: &mut [u8],
+) -> Result<(u32, usize), RuntimeError> {
+    assert!(name.last() == Some(&0), "variable name has to end in a nul");
+    let runtime_services = RUNTIME_SERVICES.lock();
+    let runtime_services = runtime_services.ok_or(RuntimeError::Unavailable)?;
+
+    let mut attributes = 0;
+    let mut data_size = data.len();
+    let status = unsafe {
+        (runtime_services.get_variable)(
+            name.as_ptr(),
+            vendor,
+            &mut attributes,
+            &mut data_size,
+            data.as_mut_ptr(),
+        )
+    };
+    if status == STATUS_BUFFER_TOO_SMALL {
+        return Err(RuntimeError::BufferTooSmall { needed: data_size });
+    }
+    check_status(status)?;
+    Ok((attributes, data_size))
+}
+
+//empty data deletes the variable
+#[allow(dead_code)] //nothing in the kernel writes variables yet
+pub fn set_variable(
+    name: &[u16],
+    vendor: &Guid,
+    attributes: u32,
+    data: &[u8],
+) -> Result<(), RuntimeError> {
+    assert!(name.last() == Some(&0), "variable name has to end in a nul");
+    let runtime_services = RUNTIME_SERVICES.lock();
+    let runtime_services = runtime_services.ok_or(RuntimeError::Unavailable)?;
+
+    let status = unsafe {
+        (runtime_services.set_variable)(
+            name.as_ptr(),
+            vendor,
+            attributes,
+            data.len(),
+            data.as_ptr(),
+        )
+    };
+    check_status(status)
+}
+
+//the Boot#### entry the firmware started
+pub fn boot_current() -> Result<u16, RuntimeError> {
+    let mut name = [0u16; 12];
+    for (c, unit) in name.iter_mut().zip("BootCurrent".encode_utf16()) {
+        *c = unit;
+    }
+    let mut data = [0u8; 2];
+    get_variable(&name, &GLOBAL_VARIABLE, &mut data)?;
+    Ok(u16::from_le_bytes(data))
+}
+
+//only returns if there are no runtime services
+pub fn reset_system(reset_type: ResetType) {
+    let runtime_services = RUNTIME_SERVICES.lock();
+    if let Some(runtime_services) = *runtime_services {
+        unsafe { (runtime_services.reset_system)(reset_type, 0, 0, core::ptr::null()) };
+    }
+}
+
+fn check_status(status: usize) -> Result<(), RuntimeError> {
+    if status & STATUS_ERROR_BIT != 0 {
+        return Err(RuntimeError::Status(status));
+    }
+    Ok(())
+}
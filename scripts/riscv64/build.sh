@@ -0,0 +1,9 @@
+#!/usr/bin/env bash
+set -e
+
+#no uefi target for riscv64, so the bootloader is a position independent ELF with its own pe header
+#that objcopy turns into the .efi. the prebuilt core isn't PIC, hence notext
+RUSTFLAGS="-C relocation-model=pie -C link-arg=-pie -C link-arg=--no-dynamic-linker -C link-arg=--no-relax -C link-arg=-znotext" \
+    cargo build --bin bootloader --target riscv64gc-unknown-none-elf
+llvm-objcopy -O binary ../../target/riscv64gc-unknown-none-elf/debug/bootloader ../../target/riscv64gc-unknown-none-elf/debug/bootloader.efi
+cargo build --bin kernel --target riscv64gc-unknown-none-elf
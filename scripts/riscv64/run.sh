@@ -0,0 +1,38 @@
+#!/usr/bin/env bash
+set -e
+
+./build.sh
+
+#the riscv64 kernel still starts in machine mode at 0x80000000 and doesn't take boot info, so by
+#default it boots on its own without firmware
+if [ "$1" != "uefi" ]; then
+    qemu-system-riscv64 \
+        -machine virt \
+        -bios none \
+        -m 1G \
+        -nographic \
+        -kernel ../../target/riscv64gc-unknown-none-elf/debug/kernel
+    exit
+fi
+
+#./run.sh uefi goes through edk2 and the bootloader, which only gets as far as handing over
+rm --force --recursive ./esp
+mkdir --parents ./esp/efi/boot
+mkdir --parents ./esp/efi/kernel
+
+cp ../../target/riscv64gc-unknown-none-elf/debug/bootloader.efi ./esp/efi/boot/bootriscv64.efi
+cp ../../target/riscv64gc-unknown-none-elf/debug/kernel ./esp/efi/kernel
+
+#edk2's RISCV_VIRT_CODE.fd and RISCV_VIRT_VARS.fd, both padded to 32MiB for the pflash
+FIRMWARE=${FIRMWARE:-$HOME/.guix-home/profile/share/firmware}
+
+printf "hihi\n你好\b" > ./esp/poo.txt
+qemu-system-riscv64 \
+    -machine virt,pflash0=andy_code,pflash1=andy_vars \
+    -m 1G \
+    -nographic \
+    -blockdev node-name=andy_code,driver=file,filename=$FIRMWARE/RISCV_VIRT_CODE.fd,read-only=on \
+    -blockdev node-name=andy_vars,driver=file,filename=$FIRMWARE/RISCV_VIRT_VARS.fd,read-only=on \
+    -netdev user,id=andy_net,tftp=./esp \
+    -device virtio-net-pci,netdev=andy_net \
+    -drive format=raw,file=fat:rw:esp
@@ -16,9 +16,11 @@ printf "hihi\n你好\b" > ./esp/poo.txt
 qemu-system-x86_64 \
     -enable-kvm \
     -nodefaults \
-    -nographic \
+    -vga std \
     -chardev file,id=andy_out,path="/tmp/andy_log.txt" \
     -serial chardev:andy_out \
     -debugcon mon:stdio \
+    -netdev user,id=andy_net,tftp=./esp \
+    -device virtio-net-pci,netdev=andy_net \
     -drive if=pflash,format=raw,readonly=on,file=$HOME/.guix-home/profile/share/firmware/ovmf_x64.bin \
     -drive format=raw,file=fat:rw:esp